@@ -0,0 +1,359 @@
+use std::ops::Range;
+
+// SETBIT / GETBIT offsets are limited to 2^32 bits, i.e. a 512MB string
+pub const MAX_BIT_OFFSET: u64 = (1 << 32) - 1;
+
+/// Whether BITCOUNT / BITPOS ranges are given in bytes or in bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+// Bits are numbered from the most significant bit of the first byte, as in Redis
+fn mask(offset: usize) -> u8 {
+    0x80 >> (offset % 8)
+}
+
+pub fn get_bit(data: &[u8], offset: usize) -> bool {
+    data.get(offset / 8).is_some_and(|byte| byte & mask(offset) != 0)
+}
+
+/// Sets the bit at `offset`, growing `data` with zero bytes when needed. Returns the old bit.
+pub fn set_bit(data: &mut Vec<u8>, offset: usize, on: bool) -> bool {
+    let byte = offset / 8;
+    if data.len() <= byte {
+        data.resize(byte + 1, 0);
+    }
+    let old = data[byte] & mask(offset) != 0;
+    if on {
+        data[byte] |= mask(offset);
+    } else {
+        data[byte] &= !mask(offset);
+    }
+    old
+}
+
+/// Counts the set bits among the bit positions in `bits`
+pub fn count_bits(data: &[u8], bits: Range<usize>) -> usize {
+    let mut count = 0;
+    let mut offset = bits.start;
+    while offset < bits.end {
+        // Count whole bytes at once once the position is byte aligned
+        if offset.is_multiple_of(8) && offset + 8 <= bits.end {
+            let last = bits.end / 8;
+            count += data[offset / 8..last]
+                .iter()
+                .map(|byte| byte.count_ones() as usize)
+                .sum::<usize>();
+            offset = last * 8;
+            continue;
+        }
+        if get_bit(data, offset) {
+            count += 1;
+        }
+        offset += 1;
+    }
+    count
+}
+
+/// Position of the first bit equal to `bit` among the positions in `bits`
+pub fn find_bit(data: &[u8], bit: bool, bits: Range<usize>) -> Option<usize> {
+    // A byte with all bits opposite to the one we look for can be skipped whole
+    let skip = if bit { 0x00 } else { 0xff };
+    let mut offset = bits.start;
+    while offset < bits.end {
+        if offset.is_multiple_of(8) && offset + 8 <= bits.end && data[offset / 8] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(data, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+/// Combines the sources byte by byte; shorter sources behave as if padded with zero bytes
+pub fn bit_op(op: BitOperation, sources: &[&[u8]]) -> Vec<u8> {
+    let len = sources
+        .iter()
+        .map(|source| source.len())
+        .max()
+        .unwrap_or(0);
+    let byte_at = |source: &[u8], idx: usize| source.get(idx).copied().unwrap_or(0);
+    (0..len)
+        .map(|idx| {
+            let mut bytes = sources.iter().map(|source| byte_at(source, idx));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOperation::And => bytes.fold(first, |acc, byte| acc & byte),
+                BitOperation::Or => bytes.fold(first, |acc, byte| acc | byte),
+                BitOperation::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                BitOperation::Not => !first,
+            }
+        })
+        .collect()
+}
+
+/// An integer type of a BITFIELD subcommand, such as i5 or u16
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitFieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitFieldOp {
+    Get(BitFieldType, usize),
+    Set(BitFieldType, usize, i64),
+    IncrBy(BitFieldType, usize, i64),
+    Overflow(BitFieldOverflow),
+}
+
+impl BitFieldType {
+    /// Parses "i1".."i64" and "u1".."u63"; u64 can't be represented in a signed reply
+    pub fn parse(arg: &[u8]) -> Option<BitFieldType> {
+        let (signed, max_bits) = match arg.first()? {
+            b'i' | b'I' => (true, 64),
+            b'u' | b'U' => (false, 63),
+            _ => {
+                return None;
+            }
+        };
+        let bits: u32 = std::str::from_utf8(&arg[1..]).ok()?.parse().ok()?;
+        (1..=max_bits).contains(&bits).then_some(BitFieldType { signed, bits })
+    }
+
+    fn min(&self) -> i128 {
+        if self.signed { -(1i128 << (self.bits - 1)) } else { 0 }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed { (1i128 << (self.bits - 1)) - 1 } else { (1i128 << self.bits) - 1 }
+    }
+
+    // Interprets the raw bits read from the string as a value of this type
+    fn decode(&self, raw: u64) -> i64 {
+        if self.signed && self.bits < 64 && raw & (1 << (self.bits - 1)) != 0 {
+            // Sign extend
+            (raw | (u64::MAX << self.bits)) as i64
+        } else {
+            raw as i64
+        }
+    }
+
+    /// Brings `value` into the range of this type according to `overflow`, or None when it
+    /// doesn't fit and the overflow mode is FAIL
+    fn fit(&self, value: i128, overflow: BitFieldOverflow) -> Option<i64> {
+        if value >= self.min() && value <= self.max() {
+            return Some(value as i64);
+        }
+        match overflow {
+            BitFieldOverflow::Wrap => {
+                let raw = (value & ((1i128 << self.bits) - 1)) as u64;
+                Some(self.decode(raw))
+            }
+            BitFieldOverflow::Sat => Some(value.clamp(self.min(), self.max()) as i64),
+            BitFieldOverflow::Fail => None,
+        }
+    }
+}
+
+fn get_field(data: &[u8], offset: usize, field: BitFieldType) -> i64 {
+    let raw = (0..field.bits as usize).fold(0u64, |acc, idx| {
+        (acc << 1) | (get_bit(data, offset + idx) as u64)
+    });
+    field.decode(raw)
+}
+
+fn set_field(data: &mut Vec<u8>, offset: usize, field: BitFieldType, value: i64) {
+    let raw = value as u64;
+    for idx in 0..field.bits as usize {
+        let bit = (raw >> (field.bits as usize - 1 - idx)) & 1 == 1;
+        set_bit(data, offset + idx, bit);
+    }
+}
+
+/// Runs BITFIELD subcommands in order against `data`, returning one result per GET, SET and
+/// INCRBY. SET yields the previous value, INCRBY the new one, and both yield None when the
+/// write overflowed in FAIL mode (the field is then left untouched).
+pub fn bit_field(data: &mut Vec<u8>, ops: &[BitFieldOp]) -> Vec<Option<i64>> {
+    // Writes grow the string up front so it covers the furthest field being written, even
+    // when that write later fails on overflow
+    let needed = ops
+        .iter()
+        .filter_map(|op| {
+            match op {
+                BitFieldOp::Set(field, offset, _) | BitFieldOp::IncrBy(field, offset, _) => {
+                    Some((offset + (field.bits as usize)).div_ceil(8))
+                }
+                _ => None,
+            }
+        })
+        .max();
+    if let Some(needed) = needed {
+        if data.len() < needed {
+            data.resize(needed, 0);
+        }
+    }
+
+    let mut overflow = BitFieldOverflow::Wrap;
+    let mut results = Vec::new();
+    for op in ops {
+        match *op {
+            BitFieldOp::Overflow(mode) => {
+                overflow = mode;
+            }
+            BitFieldOp::Get(field, offset) => {
+                results.push(Some(get_field(data, offset, field)));
+            }
+            BitFieldOp::Set(field, offset, value) => {
+                let old = get_field(data, offset, field);
+                let new = field.fit(value as i128, overflow);
+                if let Some(new) = new {
+                    set_field(data, offset, field, new);
+                }
+                results.push(new.map(|_| old));
+            }
+            BitFieldOp::IncrBy(field, offset, increment) => {
+                let old = get_field(data, offset, field);
+                let new = field.fit((old as i128) + (increment as i128), overflow);
+                if let Some(new) = new {
+                    set_field(data, offset, field, new);
+                }
+                results.push(new);
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> BitFieldType {
+        BitFieldType::parse(name.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_field_types() {
+        assert_eq!(field("i8"), BitFieldType { signed: true, bits: 8 });
+        assert_eq!(field("U63"), BitFieldType { signed: false, bits: 63 });
+        assert_eq!(field("i64"), BitFieldType { signed: true, bits: 64 });
+        for invalid in ["u64", "i65", "i0", "u", "x8", "i-1", ""] {
+            assert_eq!(BitFieldType::parse(invalid.as_bytes()), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn wraps_by_default() {
+        let mut data = Vec::new();
+        let results = bit_field(
+            &mut data,
+            &[
+                BitFieldOp::Set(field("u8"), 0, 255),
+                BitFieldOp::IncrBy(field("u8"), 0, 10),
+                BitFieldOp::Set(field("i8"), 8, 200),
+                BitFieldOp::IncrBy(field("i8"), 8, -100),
+                BitFieldOp::Get(field("i8"), 8),
+            ]
+        );
+        // 255 + 10 wraps to 9; 200 is stored as -56 and -56 - 100 wraps to 100
+        assert_eq!(results, vec![Some(0), Some(9), Some(0), Some(100), Some(100)]);
+        assert_eq!(data, vec![9, 100]);
+    }
+
+    #[test]
+    fn wraps_signed_fields_of_every_width() {
+        let mut data = Vec::new();
+        let results = bit_field(
+            &mut data,
+            &[
+                BitFieldOp::Set(field("i64"), 0, i64::MAX),
+                BitFieldOp::IncrBy(field("i64"), 0, 1),
+                BitFieldOp::Set(field("i3"), 64, 3),
+                BitFieldOp::IncrBy(field("i3"), 64, 1),
+            ]
+        );
+        assert_eq!(results, vec![Some(0), Some(i64::MIN), Some(0), Some(-4)]);
+    }
+
+    #[test]
+    fn saturates_at_the_bounds_of_the_type() {
+        let mut data = Vec::new();
+        let results = bit_field(
+            &mut data,
+            &[
+                BitFieldOp::Overflow(BitFieldOverflow::Sat),
+                BitFieldOp::Set(field("u8"), 0, 300),
+                BitFieldOp::IncrBy(field("u8"), 0, 1),
+                BitFieldOp::IncrBy(field("u8"), 0, -1000),
+                BitFieldOp::Set(field("i8"), 8, 200),
+                BitFieldOp::IncrBy(field("i8"), 8, -1000),
+                BitFieldOp::Get(field("i8"), 8),
+                BitFieldOp::Set(field("i64"), 16, i64::MAX),
+                BitFieldOp::IncrBy(field("i64"), 16, i64::MAX),
+            ]
+        );
+        assert_eq!(
+            results,
+            vec![Some(0), Some(255), Some(0), Some(0), Some(-128), Some(-128), Some(0), Some(i64::MAX)]
+        );
+    }
+
+    #[test]
+    fn fails_without_touching_the_field() {
+        let mut data = Vec::new();
+        let results = bit_field(
+            &mut data,
+            &[
+                BitFieldOp::Set(field("u8"), 0, 250),
+                BitFieldOp::Overflow(BitFieldOverflow::Fail),
+                BitFieldOp::IncrBy(field("u8"), 0, 10),
+                BitFieldOp::IncrBy(field("u8"), 0, 5),
+                BitFieldOp::Set(field("i8"), 8, 200),
+                BitFieldOp::Set(field("i8"), 8, -128),
+                BitFieldOp::IncrBy(field("i8"), 8, -1),
+                BitFieldOp::Get(field("u8"), 0),
+                BitFieldOp::Get(field("i8"), 8),
+            ]
+        );
+        assert_eq!(
+            results,
+            vec![Some(0), None, Some(255), None, Some(0), None, Some(255), Some(-128)]
+        );
+        assert_eq!(data, vec![255, 0x80]);
+    }
+
+    #[test]
+    fn grows_the_string_even_when_the_write_fails() {
+        let mut data = Vec::new();
+        let results = bit_field(
+            &mut data,
+            &[
+                BitFieldOp::Overflow(BitFieldOverflow::Fail),
+                BitFieldOp::Set(field("u4"), 20, 16),
+            ]
+        );
+        assert_eq!(results, vec![None]);
+        assert_eq!(data, vec![0, 0, 0]);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::{ HashMap, VecDeque };
+use bytes::Bytes;
+use tokio::sync::oneshot;
+use crate::value::ListEnd;
+
+pub type ClientId = u64;
+
+/// What a blocked client wants done once one of its keys has data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockedOp {
+    Pop(ListEnd),
+    // BLMPOP: pop up to `count` elements from the key that became ready
+    MultiPop {
+        end: ListEnd,
+        count: usize,
+    },
+    // BLMOVE: pop from the key that became ready and push the element to `destination`
+    Move {
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+}
+
+#[derive(Debug)]
+pub struct BlockedClient {
+    pub keys: Vec<Bytes>,
+    pub op: BlockedOp,
+    // Receives the reply that serving the client produced
+    pub sender: oneshot::Sender<Vec<u8>>,
+}
+
+/// Clients blocked on keys, kept per key in the order they started waiting.
+///
+/// Instead of blocked clients polling the keyspace, whoever pushes to a key hands the data
+/// straight to the client that has been waiting the longest. Everything here is only touched
+/// while holding the database lock, which makes "pop and deliver" atomic.
+#[derive(Debug, Default)]
+pub struct BlockingState {
+    next_id: ClientId,
+    clients: HashMap<ClientId, BlockedClient>,
+    queues: HashMap<Bytes, VecDeque<ClientId>>,
+}
+
+impl BlockingState {
+    /// Registers a client waiting on `keys`. The reply is delivered through the returned receiver.
+    pub fn block(&mut self, keys: Vec<Bytes>, op: BlockedOp) -> (ClientId, oneshot::Receiver<Vec<u8>>) {
+        let (sender, receiver) = oneshot::channel();
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        self.clients.insert(id, BlockedClient { keys, op, sender });
+        (id, receiver)
+    }
+
+    /// Stops waiting for a client, e.g. on timeout. Returns false when the client had already
+    /// been served, in which case its reply is waiting in the receiver.
+    pub fn unblock(&mut self, id: ClientId) -> bool {
+        match self.clients.remove(&id) {
+            Some(client) => {
+                self.forget(id, &client.keys);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn has_waiters(&self, key: &Bytes) -> bool {
+        self.queues.contains_key(key)
+    }
+
+    /// Takes the longest waiting client blocked on `key` out of every queue it is in
+    pub fn pop_waiter(&mut self, key: &Bytes) -> Option<BlockedClient> {
+        loop {
+            let id = self.queues.get_mut(key)?.pop_front();
+            let Some(id) = id else {
+                self.queues.remove(key);
+                return None;
+            };
+            if let Some(client) = self.clients.remove(&id) {
+                self.forget(id, &client.keys);
+                // A closed sender means the waiting side is gone, so it can't be served
+                if !client.sender.is_closed() {
+                    return Some(client);
+                }
+            }
+        }
+    }
+
+    fn forget(&mut self, id: ClientId, keys: &[Bytes]) {
+        for key in keys {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|waiting| *waiting != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+    }
+}
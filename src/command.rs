@@ -1,329 +1,907 @@
 #![allow(unused_imports)]
 use std::error::Error;
-use std::ops::Index;
+use std::ops::{ Index, Range };
+use std::str::FromStr;
 use std::vec;
 use tokio::net::TcpListener;
 use tokio::time::{ sleep_until, Instant, Duration, interval };
 use std::future::Future;
 use tokio::io::{ AsyncReadExt, AsyncWriteExt };
-use bytes::BytesMut;
-use std::collections::HashMap;
+use bytes::{ Bytes, BytesMut };
+use std::collections::{ HashMap, HashSet, VecDeque };
 use std::sync::{ Arc, Mutex };
+use tokio::sync::oneshot;
 // use resp_async::ValueDecoder;
 use resp::{ Decoder, Value };
 use std::io::BufReader;
-pub const DEFAULT_EXPIRY: u64 = 1000;
-use crate::value::{ RedisValue, StreamEntry };
+use crate::bitmap::{
+    self,
+    BitFieldOp,
+    BitFieldOverflow,
+    BitFieldType,
+    BitOperation,
+    BitUnit,
+};
+use crate::blocking::{ BlockedOp, ClientId };
+use crate::db::{ now_ms, Database };
+use crate::reply;
+use crate::random;
+use crate::hash::Hash;
+use crate::scan;
+use crate::set::Set;
+use crate::zset::{ LexBound, LexRange, ScoreRange, ZSet };
+use crate::value::{
+    format_float,
+    parse_f64_strict,
+    parse_i64_strict,
+    ListEnd,
+    RedisValue,
+    StreamEntry,
+    MAX_STRING_LEN,
+};
+
+// A negative count of SRANDMEMBER / HRANDFIELD may pick the same element again and again, so
+// nothing but this limit bounds the reply that is built while holding the keyspace lock
+const MAX_RANDOM_REPEATS: i64 = 1024 * 1024;
+
 pub enum Command {
     PING,
-    ECHO(String),
-    SET(String, String),
-    SetExpiry(String, String, String, String),
-    GET(String),
-    TYPE(String),
-    LPUSH(String, Vec<String>),
-    RPUSH(String, Vec<String>),
-    LRANGE(String, isize, isize),
-    LLEN(String),
-    LPOP(String, Option<isize>),
-    BLPOP(String, f64),
-    XADD(String, String, HashMap<String, String>),
+    ECHO(Bytes),
+    SET(Bytes, Bytes, SetOptions),
+    GET(Bytes),
+    TYPE(Bytes),
+    LPUSH(Bytes, Vec<Bytes>),
+    RPUSH(Bytes, Vec<Bytes>),
+    LRANGE(Bytes, isize, isize),
+    LLEN(Bytes),
+    LPOP(Bytes, Option<usize>),
+    RPOP(Bytes, Option<usize>),
+    LMPOP(Vec<Bytes>, ListEnd, usize),
+    BLMPOP(Vec<Bytes>, ListEnd, usize, Option<Duration>),
+    LMOVE(Bytes, Bytes, ListEnd, ListEnd),
+    BLMOVE(Bytes, Bytes, ListEnd, ListEnd, Option<Duration>),
+    LPUSHX(Bytes, Vec<Bytes>),
+    RPUSHX(Bytes, Vec<Bytes>),
+    LINDEX(Bytes, isize),
+    LSET(Bytes, isize, Bytes),
+    // key, insert before (true) or after the pivot, pivot, element
+    LINSERT(Bytes, bool, Bytes, Bytes),
+    LREM(Bytes, i64, Bytes),
+    LTRIM(Bytes, isize, isize),
+    LPOS(Bytes, Bytes, LposOptions),
+    // A timeout of None blocks forever
+    BLPOP(Vec<Bytes>, Option<Duration>),
+    BRPOP(Vec<Bytes>, Option<Duration>),
+    XADD(Bytes, String, HashMap<Bytes, Bytes>),
+    EXPIRE(Bytes, i64, ExpireUnit, ExpireCondition),
+    TTL(Bytes),
+    PTTL(Bytes),
+    EXPIRETIME(Bytes),
+    PEXPIRETIME(Bytes),
+    PERSIST(Bytes),
+    INCRBY(Bytes, i64),
+    INCRBYFLOAT(Bytes, f64),
+    APPEND(Bytes, Bytes),
+    STRLEN(Bytes),
+    GETRANGE(Bytes, isize, isize),
+    SETRANGE(Bytes, usize, Bytes),
+    GETDEL(Bytes),
+    GETEX(Bytes, Option<ExpiryOption>),
+    GETSET(Bytes, Bytes),
+    MGET(Vec<Bytes>),
+    MSET(Vec<(Bytes, Bytes)>),
+    MSETNX(Vec<(Bytes, Bytes)>),
+    SETBIT(Bytes, usize, bool),
+    GETBIT(Bytes, usize),
+    BITCOUNT(Bytes, Option<(isize, isize, BitUnit)>),
+    BITPOS(Bytes, bool, Option<isize>, Option<isize>, BitUnit),
+    BITOP(BitOperation, Bytes, Vec<Bytes>),
+    BITFIELD(Bytes, Vec<BitFieldOp>),
+    HSET(Bytes, Vec<(Bytes, Bytes)>),
+    HMSET(Bytes, Vec<(Bytes, Bytes)>),
+    HSETNX(Bytes, Bytes, Bytes),
+    HGET(Bytes, Bytes),
+    HMGET(Bytes, Vec<Bytes>),
+    HDEL(Bytes, Vec<Bytes>),
+    HGETALL(Bytes),
+    HEXISTS(Bytes, Bytes),
+    HLEN(Bytes),
+    HKEYS(Bytes),
+    HVALS(Bytes),
+    HINCRBY(Bytes, Bytes, i64),
+    HINCRBYFLOAT(Bytes, Bytes, f64),
+    HSTRLEN(Bytes, Bytes),
+    // key, amount, unit, condition, fields
+    HEXPIRE(Bytes, i64, ExpireUnit, ExpireCondition, Vec<Bytes>),
+    HTTL(Bytes, Vec<Bytes>),
+    HPTTL(Bytes, Vec<Bytes>),
+    HEXPIRETIME(Bytes, Vec<Bytes>),
+    HPEXPIRETIME(Bytes, Vec<Bytes>),
+    HPERSIST(Bytes, Vec<Bytes>),
+    HGETEX(Bytes, Option<ExpiryOption>, Vec<Bytes>),
+    // key, count, WITHVALUES. Without a count a single field is replied as a bulk string.
+    HRANDFIELD(Bytes, Option<i64>, bool),
+    HSCAN(Bytes, u64, ScanOptions),
+    SADD(Bytes, Vec<Bytes>),
+    SREM(Bytes, Vec<Bytes>),
+    SMEMBERS(Bytes),
+    SISMEMBER(Bytes, Bytes),
+    SMISMEMBER(Bytes, Vec<Bytes>),
+    SCARD(Bytes),
+    // Without a count a single member is replied as a bulk string
+    SPOP(Bytes, Option<usize>),
+    SRANDMEMBER(Bytes, Option<i64>),
+    SMOVE(Bytes, Bytes, Bytes),
+    SINTER(Vec<Bytes>),
+    SUNION(Vec<Bytes>),
+    SDIFF(Vec<Bytes>),
+    SINTERSTORE(Bytes, Vec<Bytes>),
+    SUNIONSTORE(Bytes, Vec<Bytes>),
+    SDIFFSTORE(Bytes, Vec<Bytes>),
+    // keys, LIMIT (0 means no limit)
+    SINTERCARD(Vec<Bytes>, usize),
+    // OBJECT ENCODING key
+    OBJECTENCODING(Bytes),
+    ZADD(Bytes, ZaddOptions, Vec<(f64, Bytes)>),
+    ZINCRBY(Bytes, f64, Bytes),
+    ZREM(Bytes, Vec<Bytes>),
+    ZSCORE(Bytes, Bytes),
+    ZMSCORE(Bytes, Vec<Bytes>),
+    ZCARD(Bytes),
+    // key, member, WITHSCORE
+    ZRANK(Bytes, Bytes, bool),
+    ZREVRANK(Bytes, Bytes, bool),
+    ZCOUNT(Bytes, ScoreRange),
+    // key, what to select, WITHSCORES. The legacy ZRANGEBYSCORE etc. parse into this too.
+    ZRANGE(Bytes, Box<ZRangeSpec>, bool),
+    // destination, source, what to select
+    ZRANGESTORE(Bytes, Bytes, Box<ZRangeSpec>),
+    // destination, inputs
+    ZUNIONSTORE(Bytes, ZCombineSpec),
+    ZINTERSTORE(Bytes, ZCombineSpec),
+    ZDIFFSTORE(Bytes, ZCombineSpec),
+    // inputs, WITHSCORES
+    ZUNION(ZCombineSpec, bool),
+    ZINTER(ZCombineSpec, bool),
+    ZDIFF(ZCombineSpec, bool),
+    ERROR(String),
     UNKNOWN,
 }
 
+/// Flags accepted by SET after the key and value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SetOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub get: bool,
+    pub expiry: Option<ExpiryOption>,
+}
+
+/// What a command that rewrites a key does to its deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryOption {
+    KeepTtl,
+    Persist,
+    // Relative to the time the command runs, in milliseconds
+    After(u64),
+    // Absolute Unix time in milliseconds
+    At(u64),
+}
+
+impl ExpiryOption {
+    /// The absolute deadline this option sets, if it sets one
+    pub fn deadline(&self) -> Option<u64> {
+        match self {
+            ExpiryOption::After(ms) => Some(now_ms().saturating_add(*ms)),
+            ExpiryOption::At(at) => Some(*at),
+            ExpiryOption::KeepTtl | ExpiryOption::Persist => None,
+        }
+    }
+}
+
+/// RANK / COUNT / MAXLEN of LPOS. A `count` of None means a single position is replied
+/// instead of an array, and zero for `count` or `maxlen` means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LposOptions {
+    pub rank: i64,
+    pub count: Option<usize>,
+    pub maxlen: usize,
+}
+
+/// MATCH / COUNT / NOVALUES of the SCAN family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    pub novalues: bool,
+}
+
+/// Flags accepted by ZADD before the score / member pairs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ZaddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+    pub incr: bool,
+}
+
+/// Which members of a sorted set ZRANGE selects
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZRangeBy {
+    // Start and stop indexes, negative ones counting from the end as in LRANGE
+    Rank(isize, isize),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// A parsed ZRANGE: the selection, whether it is read from the highest score down, and the
+/// LIMIT offset and count (a negative count meaning everything after the offset)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZRangeSpec {
+    pub by: ZRangeBy,
+    pub rev: bool,
+    pub limit: Option<(i64, i64)>,
+}
+
+/// How SINTER / SUNION / SDIFF and their STORE forms combine their sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// How ZUNION / ZINTER and their STORE forms merge the scores a member has in several inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(&self, acc: f64, score: f64) -> f64 {
+        match self {
+            // inf + -inf is NaN, which Redis turns into 0
+            Aggregate::Sum => {
+                let sum = acc + score;
+                if sum.is_nan() { 0.0 } else { sum }
+            }
+            Aggregate::Min => acc.min(score),
+            Aggregate::Max => acc.max(score),
+        }
+    }
+}
+
+/// The inputs of ZUNION / ZINTER / ZDIFF and their STORE forms: the keys, one weight per key
+/// and how scores are aggregated. ZDIFF takes neither WEIGHTS nor AGGREGATE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZCombineSpec {
+    pub keys: Vec<Bytes>,
+    pub weights: Vec<f64>,
+    pub aggregate: Aggregate,
+}
+
+/// How the numeric argument of the EXPIRE family is interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireUnit {
+    Seconds,
+    Milliseconds,
+    UnixSeconds,
+    UnixMilliseconds,
+}
+
+impl ExpireUnit {
+    // Name of the key command taking this unit, as used in error messages
+    fn command_name(&self) -> &'static str {
+        match self {
+            ExpireUnit::Seconds => "expire",
+            ExpireUnit::Milliseconds => "pexpire",
+            ExpireUnit::UnixSeconds => "expireat",
+            ExpireUnit::UnixMilliseconds => "pexpireat",
+        }
+    }
+
+    /// The absolute deadline in Unix ms that `amount` stands for, None when it overflows
+    fn deadline(&self, amount: i64, now: i64) -> Option<i64> {
+        match self {
+            ExpireUnit::Seconds => amount.checked_mul(1000).and_then(|ms| ms.checked_add(now)),
+            ExpireUnit::Milliseconds => amount.checked_add(now),
+            ExpireUnit::UnixSeconds => amount.checked_mul(1000),
+            ExpireUnit::UnixMilliseconds => Some(amount),
+        }
+    }
+}
+
+/// The NX / XX / GT / LT flags of the EXPIRE family. XX may be combined with GT or LT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExpireCondition {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+}
+
+impl ExpireCondition {
+    /// Whether `deadline` may replace the `current` one. No deadline counts as living
+    /// forever for GT / LT.
+    fn allows(&self, current: Option<i64>, deadline: i64) -> bool {
+        (!self.nx || current.is_none()) &&
+            (!self.xx || current.is_some()) &&
+            (!self.gt || current.is_some_and(|at| deadline > at)) &&
+            (!self.lt || current.is_none_or(|at| deadline < at))
+    }
+}
+
 impl Command {
     pub fn from_value(value: Value) -> Command {
-        match value {
-            Value::Array(arr) if !arr.is_empty() => {
-                // Extract string from the first Value
-                if let Value::Bulk(command) = &arr[0] {
-                    let cmd = command.clone();
-                    match cmd.to_uppercase().as_str() {
-                        "PING" => Command::PING,
-                        "ECHO" if arr.len() > 1 => {
-                            if let Value::Bulk(msg_bytes) = &arr[1] {
-                                Command::ECHO(msg_bytes.clone())
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "SET" if arr.len() == 3 => {
-                            if
-                                let (Value::Bulk(key_bytes), Value::Bulk(val_bytes)) = (
-                                    &arr[1],
-                                    &arr[2],
-                                )
-                            {
-                                Command::SET(key_bytes.clone(), val_bytes.clone())
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "SET" if arr.len() == 5 => {
-                            if
-                                let (
-                                    Value::Bulk(key_bytes),
-                                    Value::Bulk(val_bytes),
-                                    Value::Bulk(expiry_command),
-                                    Value::Bulk(timeout),
-                                ) = (&arr[1], &arr[2], &arr[3], &arr[4])
-                            {
-                                Command::SetExpiry(
-                                    key_bytes.clone(),
-                                    val_bytes.clone(),
-                                    expiry_command.clone(),
-                                    timeout.clone()
-                                )
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "RPUSH" if arr.len() > 1 => {
-                            let mut value_array: Vec<String> = vec![];
-                            if let Value::Bulk(key_bytes) = &arr[1] {
-                                for (i, value) in arr.iter().enumerate() {
-                                    if i > 1 {
-                                        if let Value::Bulk(val) = value {
-                                            println!("value parsed: {:?}", value);
-                                            value_array.push(val.clone());
-                                        } else {
-                                            eprintln!("{:?}: was not parsed correctly", value);
-                                        }
-                                    }
-                                }
-                                return Command::RPUSH(key_bytes.clone(), value_array);
-                            } else {
-                                eprintln!("The key_bytes {:?}: was not parsed correctly", &arr[1]);
-                            }
-
-                            Command::UNKNOWN
-                        }
-                        "LPUSH" if arr.len() > 1 => {
-                            let mut value_array: Vec<String> = vec![];
-                            if let Value::Bulk(key_bytes) = &arr[1] {
-                                for (i, value) in arr.iter().enumerate() {
-                                    if i > 1 {
-                                        if let Value::Bulk(val) = value {
-                                            println!("value parsed: {:?}", value);
-                                            value_array.push(val.clone());
-                                        } else {
-                                            eprintln!("{:?}: was not parsed correctly", value);
-                                        }
-                                    }
-                                }
-                                return Command::LPUSH(key_bytes.clone(), value_array);
-                            } else {
-                                eprintln!("The key_bytes {:?}: was not parsed correctly", &arr[1]);
-                            }
+        // Every argument of a request is a bulk string; collect them as raw bytes so keys
+        // and values can hold arbitrary binary data
+        let arr: Vec<Bytes> = match value {
+            Value::Array(items) if !items.is_empty() => {
+                match items.into_iter().map(bulk_bytes).collect::<Option<Vec<Bytes>>>() {
+                    Some(arr) => arr,
+                    None => {
+                        return Command::UNKNOWN;
+                    }
+                }
+            }
+            _ => {
+                return Command::UNKNOWN;
+            }
+        };
 
-                            // Command::LPUSH(, ())
-                            Command::UNKNOWN
-                        }
-                        "XADD" if arr.len() >= 5 => {
-                            if
-                                let (Value::Bulk(key_bytes), Value::Bulk(entry_id_bytes)) = (
-                                    &arr[1],
-                                    &arr[2],
-                                )
-                            {
-                                if (arr.len() - 3) % 2 != 0 {
-                                    return Command::UNKNOWN;
-                                }
-                                let mut field_pairs = HashMap::new();
-                                let mut idx = 3;
-                                while idx + 1 < arr.len() {
-                                    match (&arr[idx], &arr[idx + 1]) {
-                                        (Value::Bulk(field), Value::Bulk(val)) => {
-                                            field_pairs.insert(field.clone(), val.clone());
-                                            idx += 2;
-                                        }
-                                        _ => {
-                                            return Command::UNKNOWN;
-                                        }
-                                    }
-                                }
-                                Command::XADD(
-                                    key_bytes.clone(),
-                                    entry_id_bytes.clone(),
-                                    field_pairs
-                                )
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "LRANGE" if arr.len() > 1 => {
-                            if
-                                let (Value::Bulk(key_bytes), Value::Bulk(start), Value::Bulk(end)) =
-                                    (&arr[1], &arr[2], &arr[3])
-                            {
-                                let start: isize = start.parse().unwrap();
-                                let end: isize = end.parse().unwrap();
-                                Command::LRANGE(key_bytes.clone(), start, end)
-                            } else {
-                                eprintln!("The values for LRANGE are not correctly formatted");
-                                Command::UNKNOWN
-                            }
-                        }
-                        "LLEN" if arr.len() > 1 => {
-                            if let Value::Bulk(key_bytes) = &arr[1] {
-                                Command::LLEN(key_bytes.clone())
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "LPOP" if arr.len() == 2 => {
-                            if let Value::Bulk(key_bytes) = &arr[1] {
-                                Command::LPOP(key_bytes.clone(), None)
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "LPOP" if arr.len() > 2 => {
-                            if
-                                let (Value::Bulk(key_bytes), Value::Bulk(to_remove)) = (
-                                    &arr[1],
-                                    &arr[2],
-                                )
-                            {
-                                let to_remove = to_remove.parse().unwrap();
-                                Command::LPOP(key_bytes.clone(), Some(to_remove))
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "GET" if arr.len() > 1 => {
-                            if let Value::Bulk(key_bytes) = &arr[1] {
-                                Command::GET(key_bytes.clone())
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "TYPE" if arr.len() > 1 => {
-                            if let Value::Bulk(key_bytes) = &arr[1] {
-                                Command::TYPE(key_bytes.clone())
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        "BLPOP" if arr.len() > 2 => {
-                            if
-                                let (Value::Bulk(key_bytes), Value::Bulk(timeout)) = (
-                                    &arr[1],
-                                    &arr[2],
-                                )
-                            {
-                                let timeout: f64 = timeout.parse().unwrap_or(0.0);
-                                Command::BLPOP(key_bytes.clone(), timeout)
-                            } else {
-                                Command::UNKNOWN
-                            }
-                        }
-                        _ => Command::UNKNOWN,
+        let cmd = String::from_utf8_lossy(&arr[0]).to_uppercase();
+        match cmd.as_str() {
+            "PING" => Command::PING,
+            "ECHO" if arr.len() > 1 => Command::ECHO(arr[1].clone()),
+            "SET" => parse_set(&arr),
+            "RPUSH" if arr.len() > 2 => Command::RPUSH(arr[1].clone(), arr[2..].to_vec()),
+            "LPUSH" if arr.len() > 2 => Command::LPUSH(arr[1].clone(), arr[2..].to_vec()),
+            "RPUSHX" if arr.len() > 2 => Command::RPUSHX(arr[1].clone(), arr[2..].to_vec()),
+            "LPUSHX" if arr.len() > 2 => Command::LPUSHX(arr[1].clone(), arr[2..].to_vec()),
+            "XADD" if arr.len() >= 5 => {
+                if !(arr.len() - 3).is_multiple_of(2) {
+                    return Command::UNKNOWN;
+                }
+                let Ok(entry_id) = String::from_utf8(arr[2].to_vec()) else {
+                    return Command::UNKNOWN;
+                };
+                let field_pairs = arr[3..]
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                Command::XADD(arr[1].clone(), entry_id, field_pairs)
+            }
+            "LRANGE" if arr.len() > 3 => {
+                match (parse_arg(&arr[2]), parse_arg(&arr[3])) {
+                    (Some(start), Some(end)) => Command::LRANGE(arr[1].clone(), start, end),
+                    _ => not_an_integer(),
+                }
+            }
+            "LLEN" if arr.len() > 1 => Command::LLEN(arr[1].clone()),
+            "LPOP" | "RPOP" if arr.len() == 2 || arr.len() == 3 => {
+                let count = match arr.get(2).map(|count| parse_arg::<i64>(count)) {
+                    None => None,
+                    Some(Some(count)) if count >= 0 => Some(count as usize),
+                    Some(Some(_)) => {
+                        return Command::ERROR(
+                            "ERR value is out of range, must be positive".to_string()
+                        );
+                    }
+                    Some(None) => {
+                        return not_an_integer();
+                    }
+                };
+                if cmd == "LPOP" { Command::LPOP(arr[1].clone(), count) } else { Command::RPOP(arr[1].clone(), count) }
+            }
+            "LINDEX" if arr.len() == 3 => {
+                match parse_arg(&arr[2]) {
+                    Some(index) => Command::LINDEX(arr[1].clone(), index),
+                    None => not_an_integer(),
+                }
+            }
+            "LSET" if arr.len() == 4 => {
+                match parse_arg(&arr[2]) {
+                    Some(index) => Command::LSET(arr[1].clone(), index, arr[3].clone()),
+                    None => not_an_integer(),
+                }
+            }
+            "LINSERT" if arr.len() == 5 => {
+                let before = match String::from_utf8_lossy(&arr[2]).to_uppercase().as_str() {
+                    "BEFORE" => true,
+                    "AFTER" => false,
+                    _ => {
+                        return syntax_error();
+                    }
+                };
+                Command::LINSERT(arr[1].clone(), before, arr[3].clone(), arr[4].clone())
+            }
+            "LREM" if arr.len() == 4 => {
+                match parse_arg(&arr[2]) {
+                    Some(count) => Command::LREM(arr[1].clone(), count, arr[3].clone()),
+                    None => not_an_integer(),
+                }
+            }
+            "LTRIM" if arr.len() == 4 => {
+                match (parse_arg(&arr[2]), parse_arg(&arr[3])) {
+                    (Some(start), Some(stop)) => Command::LTRIM(arr[1].clone(), start, stop),
+                    _ => not_an_integer(),
+                }
+            }
+            "LPOS" if arr.len() >= 3 => parse_lpos(&arr),
+            "LMOVE" if arr.len() == 5 => {
+                match (parse_list_end(&arr[3]), parse_list_end(&arr[4])) {
+                    (Some(from), Some(to)) => Command::LMOVE(arr[1].clone(), arr[2].clone(), from, to),
+                    _ => syntax_error(),
+                }
+            }
+            "RPOPLPUSH" if arr.len() == 3 => {
+                Command::LMOVE(arr[1].clone(), arr[2].clone(), ListEnd::Right, ListEnd::Left)
+            }
+            "BLMOVE" if arr.len() == 6 => {
+                let (Some(from), Some(to)) = (parse_list_end(&arr[3]), parse_list_end(&arr[4])) else {
+                    return syntax_error();
+                };
+                match parse_timeout(&arr[5]) {
+                    Ok(timeout) => Command::BLMOVE(arr[1].clone(), arr[2].clone(), from, to, timeout),
+                    Err(err) => err,
+                }
+            }
+            "BRPOPLPUSH" if arr.len() == 4 => {
+                match parse_timeout(&arr[3]) {
+                    Ok(timeout) => {
+                        Command::BLMOVE(
+                            arr[1].clone(),
+                            arr[2].clone(),
+                            ListEnd::Right,
+                            ListEnd::Left,
+                            timeout
+                        )
+                    }
+                    Err(err) => err,
+                }
+            }
+            "LMPOP" if arr.len() >= 4 => parse_lmpop(&arr[1..], None),
+            "BLMPOP" if arr.len() >= 5 => {
+                match parse_timeout(&arr[1]) {
+                    Ok(timeout) => parse_lmpop(&arr[2..], Some(timeout)),
+                    Err(err) => err,
+                }
+            }
+            "LMOVE" | "RPOPLPUSH" | "BLMOVE" | "BRPOPLPUSH" | "LMPOP" | "BLMPOP" => wrong_arity(&cmd),
+            "RPUSH" | "LPUSH" | "RPUSHX" | "LPUSHX" | "LRANGE" | "LLEN" | "LPOP" | "RPOP" |
+            "LINDEX" | "LSET" | "LINSERT" | "LREM" | "LTRIM" | "LPOS" => wrong_arity(&cmd),
+            "GET" if arr.len() > 1 => Command::GET(arr[1].clone()),
+            "TYPE" if arr.len() > 1 => Command::TYPE(arr[1].clone()),
+            "BLPOP" | "BRPOP" if arr.len() >= 3 => {
+                // BLPOP key [key ...] timeout
+                let keys = arr[1..arr.len() - 1].to_vec();
+                match parse_timeout(&arr[arr.len() - 1]) {
+                    Ok(timeout) if cmd == "BLPOP" => Command::BLPOP(keys, timeout),
+                    Ok(timeout) => Command::BRPOP(keys, timeout),
+                    Err(err) => err,
+                }
+            }
+            "BLPOP" | "BRPOP" => wrong_arity(&cmd),
+            "EXPIRE" => parse_expire(&cmd, &arr, ExpireUnit::Seconds),
+            "PEXPIRE" => parse_expire(&cmd, &arr, ExpireUnit::Milliseconds),
+            "EXPIREAT" => parse_expire(&cmd, &arr, ExpireUnit::UnixSeconds),
+            "PEXPIREAT" => parse_expire(&cmd, &arr, ExpireUnit::UnixMilliseconds),
+            "TTL" if arr.len() == 2 => Command::TTL(arr[1].clone()),
+            "PTTL" if arr.len() == 2 => Command::PTTL(arr[1].clone()),
+            "EXPIRETIME" if arr.len() == 2 => Command::EXPIRETIME(arr[1].clone()),
+            "PEXPIRETIME" if arr.len() == 2 => Command::PEXPIRETIME(arr[1].clone()),
+            "PERSIST" if arr.len() == 2 => Command::PERSIST(arr[1].clone()),
+            "TTL" | "PTTL" | "EXPIRETIME" | "PEXPIRETIME" | "PERSIST" => wrong_arity(&cmd),
+            "INCR" if arr.len() == 2 => Command::INCRBY(arr[1].clone(), 1),
+            "DECR" if arr.len() == 2 => Command::INCRBY(arr[1].clone(), -1),
+            "INCRBY" if arr.len() == 3 => {
+                match parse_arg(&arr[2]) {
+                    Some(increment) => Command::INCRBY(arr[1].clone(), increment),
+                    None => not_an_integer(),
+                }
+            }
+            "DECRBY" if arr.len() == 3 => {
+                match parse_arg::<i64>(&arr[2]) {
+                    Some(i64::MIN) => Command::ERROR("ERR decrement would overflow".to_string()),
+                    Some(decrement) => Command::INCRBY(arr[1].clone(), -decrement),
+                    None => not_an_integer(),
+                }
+            }
+            "INCRBYFLOAT" if arr.len() == 3 => {
+                match parse_arg::<f64>(&arr[2]) {
+                    Some(increment) if increment.is_finite() => {
+                        Command::INCRBYFLOAT(arr[1].clone(), increment)
+                    }
+                    _ => Command::ERROR("ERR value is not a valid float".to_string()),
+                }
+            }
+            "INCR" | "DECR" | "INCRBY" | "DECRBY" | "INCRBYFLOAT" => wrong_arity(&cmd),
+            "APPEND" if arr.len() == 3 => Command::APPEND(arr[1].clone(), arr[2].clone()),
+            "STRLEN" if arr.len() == 2 => Command::STRLEN(arr[1].clone()),
+            "GETRANGE" if arr.len() == 4 => {
+                match (parse_arg(&arr[2]), parse_arg(&arr[3])) {
+                    (Some(start), Some(end)) => Command::GETRANGE(arr[1].clone(), start, end),
+                    _ => not_an_integer(),
+                }
+            }
+            "SETRANGE" if arr.len() == 4 => {
+                match parse_arg::<i64>(&arr[2]) {
+                    Some(offset) if offset < 0 => {
+                        Command::ERROR("ERR offset is out of range".to_string())
+                    }
+                    Some(offset) => Command::SETRANGE(arr[1].clone(), offset as usize, arr[3].clone()),
+                    None => not_an_integer(),
+                }
+            }
+            "GETDEL" if arr.len() == 2 => Command::GETDEL(arr[1].clone()),
+            "GETEX" if arr.len() >= 2 => parse_getex(&arr),
+            "GETSET" if arr.len() == 3 => Command::GETSET(arr[1].clone(), arr[2].clone()),
+            "APPEND" | "STRLEN" | "GETRANGE" | "SETRANGE" | "GETDEL" | "GETEX" | "GETSET" => {
+                wrong_arity(&cmd)
+            }
+            "MGET" if arr.len() >= 2 => Command::MGET(arr[1..].to_vec()),
+            "MSET" | "MSETNX" if arr.len() >= 3 && arr.len() % 2 == 1 => {
+                let pairs = arr[1..]
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                if cmd == "MSET" { Command::MSET(pairs) } else { Command::MSETNX(pairs) }
+            }
+            "MGET" | "MSET" | "MSETNX" => wrong_arity(&cmd),
+            "SETBIT" if arr.len() == 4 => {
+                let Some(offset) = parse_bit_offset(&arr[2]) else {
+                    return Command::ERROR(
+                        "ERR bit offset is not an integer or out of range".to_string()
+                    );
+                };
+                match &arr[3][..] {
+                    b"0" => Command::SETBIT(arr[1].clone(), offset, false),
+                    b"1" => Command::SETBIT(arr[1].clone(), offset, true),
+                    _ => Command::ERROR("ERR bit is not an integer or out of range".to_string()),
+                }
+            }
+            "GETBIT" if arr.len() == 3 => {
+                match parse_bit_offset(&arr[2]) {
+                    Some(offset) => Command::GETBIT(arr[1].clone(), offset),
+                    None => {
+                        Command::ERROR("ERR bit offset is not an integer or out of range".to_string())
+                    }
+                }
+            }
+            "BITCOUNT" if arr.len() >= 2 => parse_bitcount(&arr),
+            "BITPOS" if arr.len() >= 3 => parse_bitpos(&arr),
+            "BITOP" if arr.len() >= 4 => {
+                let op = match String::from_utf8_lossy(&arr[1]).to_uppercase().as_str() {
+                    "AND" => BitOperation::And,
+                    "OR" => BitOperation::Or,
+                    "XOR" => BitOperation::Xor,
+                    "NOT" if arr.len() == 4 => BitOperation::Not,
+                    "NOT" => {
+                        return Command::ERROR(
+                            "ERR BITOP NOT must be called with a single source key.".to_string()
+                        );
+                    }
+                    _ => {
+                        return syntax_error();
+                    }
+                };
+                Command::BITOP(op, arr[2].clone(), arr[3..].to_vec())
+            }
+            "BITFIELD" if arr.len() >= 2 => parse_bitfield(&arr, false),
+            "BITFIELD_RO" if arr.len() >= 2 => parse_bitfield(&arr, true),
+            "SETBIT" | "GETBIT" | "BITCOUNT" | "BITPOS" | "BITOP" | "BITFIELD" | "BITFIELD_RO" => {
+                wrong_arity(&cmd)
+            }
+            "HSET" | "HMSET" if arr.len() >= 4 && arr.len().is_multiple_of(2) => {
+                let pairs = arr[2..]
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                if cmd == "HSET" {
+                    Command::HSET(arr[1].clone(), pairs)
+                } else {
+                    Command::HMSET(arr[1].clone(), pairs)
+                }
+            }
+            "HSETNX" if arr.len() == 4 => {
+                Command::HSETNX(arr[1].clone(), arr[2].clone(), arr[3].clone())
+            }
+            "HGET" if arr.len() == 3 => Command::HGET(arr[1].clone(), arr[2].clone()),
+            "HMGET" if arr.len() >= 3 => Command::HMGET(arr[1].clone(), arr[2..].to_vec()),
+            "HDEL" if arr.len() >= 3 => Command::HDEL(arr[1].clone(), arr[2..].to_vec()),
+            "HGETALL" if arr.len() == 2 => Command::HGETALL(arr[1].clone()),
+            "HEXISTS" if arr.len() == 3 => Command::HEXISTS(arr[1].clone(), arr[2].clone()),
+            "HLEN" if arr.len() == 2 => Command::HLEN(arr[1].clone()),
+            "HKEYS" if arr.len() == 2 => Command::HKEYS(arr[1].clone()),
+            "HVALS" if arr.len() == 2 => Command::HVALS(arr[1].clone()),
+            "HINCRBY" if arr.len() == 4 => {
+                match parse_arg(&arr[3]) {
+                    Some(increment) => Command::HINCRBY(arr[1].clone(), arr[2].clone(), increment),
+                    None => not_an_integer(),
+                }
+            }
+            "HINCRBYFLOAT" if arr.len() == 4 => {
+                match parse_arg::<f64>(&arr[3]) {
+                    Some(increment) if increment.is_finite() => {
+                        Command::HINCRBYFLOAT(arr[1].clone(), arr[2].clone(), increment)
+                    }
+                    _ => Command::ERROR("ERR value is not a valid float".to_string()),
+                }
+            }
+            "HSTRLEN" if arr.len() == 3 => Command::HSTRLEN(arr[1].clone(), arr[2].clone()),
+            "HSET" | "HMSET" | "HSETNX" | "HGET" | "HMGET" | "HDEL" | "HGETALL" | "HEXISTS" |
+            "HLEN" | "HKEYS" | "HVALS" | "HINCRBY" | "HINCRBYFLOAT" | "HSTRLEN" => wrong_arity(&cmd),
+            "HEXPIRE" => parse_hexpire(&cmd, &arr, ExpireUnit::Seconds),
+            "HPEXPIRE" => parse_hexpire(&cmd, &arr, ExpireUnit::Milliseconds),
+            "HEXPIREAT" => parse_hexpire(&cmd, &arr, ExpireUnit::UnixSeconds),
+            "HPEXPIREAT" => parse_hexpire(&cmd, &arr, ExpireUnit::UnixMilliseconds),
+            "HTTL" | "HPTTL" | "HEXPIRETIME" | "HPEXPIRETIME" | "HPERSIST" if arr.len() >= 5 => {
+                let fields = match parse_fields(&arr[2..]) {
+                    Ok(fields) => fields,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let key = arr[1].clone();
+                match cmd.as_str() {
+                    "HTTL" => Command::HTTL(key, fields),
+                    "HPTTL" => Command::HPTTL(key, fields),
+                    "HEXPIRETIME" => Command::HEXPIRETIME(key, fields),
+                    "HPEXPIRETIME" => Command::HPEXPIRETIME(key, fields),
+                    _ => Command::HPERSIST(key, fields),
+                }
+            }
+            "HGETEX" if arr.len() >= 5 => parse_hgetex(&arr),
+            "HTTL" | "HPTTL" | "HEXPIRETIME" | "HPEXPIRETIME" | "HPERSIST" | "HGETEX" => {
+                wrong_arity(&cmd)
+            }
+            "HRANDFIELD" if arr.len() == 2 => Command::HRANDFIELD(arr[1].clone(), None, false),
+            "HRANDFIELD" if arr.len() == 3 || arr.len() == 4 => {
+                let count = match parse_arg::<i64>(&arr[2]) {
+                    Some(count) if count < -MAX_RANDOM_REPEATS => {
+                        return Command::ERROR("ERR value is out of range".to_string());
+                    }
+                    Some(count) => count,
+                    None => {
+                        return not_an_integer();
+                    }
+                };
+                match arr.get(3) {
+                    None => Command::HRANDFIELD(arr[1].clone(), Some(count), false),
+                    Some(flag) if flag.eq_ignore_ascii_case(b"WITHVALUES") => {
+                        Command::HRANDFIELD(arr[1].clone(), Some(count), true)
+                    }
+                    Some(_) => syntax_error(),
+                }
+            }
+            "HSCAN" if arr.len() >= 3 => {
+                match parse_scan(&arr[2..], true) {
+                    Ok((cursor, options)) => Command::HSCAN(arr[1].clone(), cursor, options),
+                    Err(err) => err,
+                }
+            }
+            "HRANDFIELD" | "HSCAN" => wrong_arity(&cmd),
+            "SADD" if arr.len() >= 3 => Command::SADD(arr[1].clone(), arr[2..].to_vec()),
+            "SREM" if arr.len() >= 3 => Command::SREM(arr[1].clone(), arr[2..].to_vec()),
+            "SMEMBERS" if arr.len() == 2 => Command::SMEMBERS(arr[1].clone()),
+            "SISMEMBER" if arr.len() == 3 => Command::SISMEMBER(arr[1].clone(), arr[2].clone()),
+            "SMISMEMBER" if arr.len() >= 3 => {
+                Command::SMISMEMBER(arr[1].clone(), arr[2..].to_vec())
+            }
+            "SCARD" if arr.len() == 2 => Command::SCARD(arr[1].clone()),
+            "SPOP" if arr.len() == 2 => Command::SPOP(arr[1].clone(), None),
+            "SPOP" if arr.len() == 3 => {
+                match parse_arg::<i64>(&arr[2]) {
+                    Some(count) if count >= 0 => Command::SPOP(arr[1].clone(), Some(count as usize)),
+                    _ => Command::ERROR("ERR value is out of range, must be positive".to_string()),
+                }
+            }
+            "SRANDMEMBER" if arr.len() == 2 => Command::SRANDMEMBER(arr[1].clone(), None),
+            "SRANDMEMBER" if arr.len() == 3 => {
+                match parse_arg::<i64>(&arr[2]) {
+                    Some(count) if count < -MAX_RANDOM_REPEATS => {
+                        Command::ERROR("ERR value is out of range".to_string())
+                    }
+                    Some(count) => Command::SRANDMEMBER(arr[1].clone(), Some(count)),
+                    None => not_an_integer(),
+                }
+            }
+            "SMOVE" if arr.len() == 4 => {
+                Command::SMOVE(arr[1].clone(), arr[2].clone(), arr[3].clone())
+            }
+            "SADD" | "SREM" | "SMEMBERS" | "SISMEMBER" | "SMISMEMBER" | "SCARD" | "SPOP" |
+            "SRANDMEMBER" | "SMOVE" => wrong_arity(&cmd),
+            "SINTER" if arr.len() >= 2 => Command::SINTER(arr[1..].to_vec()),
+            "SUNION" if arr.len() >= 2 => Command::SUNION(arr[1..].to_vec()),
+            "SDIFF" if arr.len() >= 2 => Command::SDIFF(arr[1..].to_vec()),
+            "SINTERSTORE" if arr.len() >= 3 => Command::SINTERSTORE(arr[1].clone(), arr[2..].to_vec()),
+            "SUNIONSTORE" if arr.len() >= 3 => Command::SUNIONSTORE(arr[1].clone(), arr[2..].to_vec()),
+            "SDIFFSTORE" if arr.len() >= 3 => Command::SDIFFSTORE(arr[1].clone(), arr[2..].to_vec()),
+            "SINTERCARD" if arr.len() >= 3 => parse_sintercard(&arr),
+            "SINTER" | "SUNION" | "SDIFF" | "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" |
+            "SINTERCARD" => wrong_arity(&cmd),
+            "OBJECT" if arr.len() >= 2 => {
+                let subcommand = String::from_utf8_lossy(&arr[1]).to_uppercase();
+                match subcommand.as_str() {
+                    "ENCODING" if arr.len() == 3 => Command::OBJECTENCODING(arr[2].clone()),
+                    "ENCODING" => wrong_arity("OBJECT|ENCODING"),
+                    _ => {
+                        Command::ERROR(
+                            format!(
+                                "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+                                String::from_utf8_lossy(&arr[1])
+                            )
+                        )
+                    }
+                }
+            }
+            "OBJECT" => wrong_arity(&cmd),
+            "ZADD" if arr.len() >= 4 => parse_zadd(&arr),
+            "ZINCRBY" if arr.len() == 4 => {
+                match parse_score(&arr[2]) {
+                    Some(increment) => Command::ZINCRBY(arr[1].clone(), increment, arr[3].clone()),
+                    None => Command::ERROR("ERR value is not a valid float".to_string()),
+                }
+            }
+            "ZREM" if arr.len() >= 3 => Command::ZREM(arr[1].clone(), arr[2..].to_vec()),
+            "ZSCORE" if arr.len() == 3 => Command::ZSCORE(arr[1].clone(), arr[2].clone()),
+            "ZMSCORE" if arr.len() >= 3 => Command::ZMSCORE(arr[1].clone(), arr[2..].to_vec()),
+            "ZCARD" if arr.len() == 2 => Command::ZCARD(arr[1].clone()),
+            "ZRANK" | "ZREVRANK" if arr.len() == 3 || arr.len() == 4 => {
+                let with_score = match arr.get(3) {
+                    None => false,
+                    Some(flag) if flag.eq_ignore_ascii_case(b"WITHSCORE") => true,
+                    Some(_) => {
+                        return syntax_error();
                     }
+                };
+                if cmd == "ZRANK" {
+                    Command::ZRANK(arr[1].clone(), arr[2].clone(), with_score)
                 } else {
-                    Command::UNKNOWN
+                    Command::ZREVRANK(arr[1].clone(), arr[2].clone(), with_score)
+                }
+            }
+            "ZCOUNT" if arr.len() == 4 => {
+                match parse_score_range(&arr[2], &arr[3]) {
+                    Some(range) => Command::ZCOUNT(arr[1].clone(), range),
+                    None => Command::ERROR("ERR min or max is not a float".to_string()),
+                }
+            }
+            "ZADD" | "ZINCRBY" | "ZREM" | "ZSCORE" | "ZMSCORE" | "ZCARD" | "ZRANK" | "ZREVRANK" |
+            "ZCOUNT" => wrong_arity(&cmd),
+            "ZRANGE" | "ZREVRANGE" | "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" | "ZRANGEBYLEX" |
+            "ZREVRANGEBYLEX" if arr.len() >= 4 => {
+                match parse_zrange(&cmd, &arr[2..]) {
+                    Ok((spec, with_scores)) => {
+                        Command::ZRANGE(arr[1].clone(), Box::new(spec), with_scores)
+                    }
+                    Err(err) => err,
+                }
+            }
+            "ZRANGESTORE" if arr.len() >= 5 => {
+                match parse_zrange(&cmd, &arr[3..]) {
+                    Ok((spec, _)) => {
+                        Command::ZRANGESTORE(arr[1].clone(), arr[2].clone(), Box::new(spec))
+                    }
+                    Err(err) => err,
+                }
+            }
+            "ZRANGE" | "ZREVRANGE" | "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" | "ZRANGEBYLEX" |
+            "ZREVRANGEBYLEX" | "ZRANGESTORE" => wrong_arity(&cmd),
+            "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" if arr.len() >= 4 => {
+                let (spec, _) = match parse_zcombine(&cmd, &arr[2..]) {
+                    Ok(parsed) => parsed,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                match cmd.as_str() {
+                    "ZUNIONSTORE" => Command::ZUNIONSTORE(arr[1].clone(), spec),
+                    "ZINTERSTORE" => Command::ZINTERSTORE(arr[1].clone(), spec),
+                    _ => Command::ZDIFFSTORE(arr[1].clone(), spec),
+                }
+            }
+            "ZUNION" | "ZINTER" | "ZDIFF" if arr.len() >= 3 => {
+                let (spec, with_scores) = match parse_zcombine(&cmd, &arr[1..]) {
+                    Ok(parsed) => parsed,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                match cmd.as_str() {
+                    "ZUNION" => Command::ZUNION(spec, with_scores),
+                    "ZINTER" => Command::ZINTER(spec, with_scores),
+                    _ => Command::ZDIFF(spec, with_scores),
                 }
             }
+            "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" | "ZUNION" | "ZINTER" | "ZDIFF" => {
+                wrong_arity(&cmd)
+            }
             _ => Command::UNKNOWN,
         }
     }
 
-    pub async fn get_return(&self, database: &Arc<Mutex<HashMap<String, RedisValue>>>) -> String {
+    pub async fn get_return(&self, database: &Arc<Mutex<Database>>) -> Vec<u8> {
         match self {
-            Command::PING => "+PONG\r\n".to_string(),
-            Command::ECHO(msg) => format!("${}\r\n{}\r\n", msg.len(), msg),
-            Command::SET(key, value) => {
+            Command::PING => reply::simple("PONG"),
+            Command::ECHO(msg) => reply::bulk(msg),
+            Command::SET(key, value, options) => {
                 let mut db = database.lock().unwrap();
-                let value = RedisValue::from_string(value.clone());
-                db.insert(key.clone(), value);
-                "+OK\r\n".to_string()
-            }
-            Command::SetExpiry(key, value, expiry_command, timeout) => {
-                {
-                    let mut db = database.lock().unwrap();
-                    let value = RedisValue::from_string(value.clone());
-                    db.insert(key.clone(), value);
-                } // MutexGuard is dropped here
-
-                // Clone the values before moving into spawned task
-                let db_clone = Arc::clone(database);
-                let key_clone = key.clone();
-                let expiry_command_clone = expiry_command.clone().to_lowercase();
-                let timeout_clone = timeout.clone();
-
-                tokio::spawn(async move {
-                    let timeout: u64 = timeout_clone.parse().unwrap_or(DEFAULT_EXPIRY);
-                    let expiry_time = match expiry_command_clone.as_str() {
-                        "px" => Duration::from_millis(timeout),
-                        "ex" => Duration::from_secs(timeout),
-                        _ => {
-                            eprintln!("GIVE A CORRECT TIMEOUT VALUE IDENTIFIER");
-                            Duration::from_millis(DEFAULT_EXPIRY)
-                        }
-                    };
-
-                    let now = Instant::now();
-                    let later = now + expiry_time;
-
-                    sleep_until(later).await;
+                let (exists, old_value) = match db.get(key) {
+                    Some(RedisValue::String(old)) => (true, Some(old.clone())),
+                    Some(_) if options.get => {
+                        return reply::wrong_type();
+                    }
+                    Some(_) => (true, None),
+                    None => (false, None),
+                };
+                let response = if options.get {
+                    old_value.map_or_else(reply::null_bulk, |old| reply::bulk(&old))
+                } else {
+                    reply::ok()
+                };
+                if (options.nx && exists) || (options.xx && !exists) {
+                    return if options.get { response } else { reply::null_bulk() };
+                }
 
-                    let mut db = db_clone.lock().unwrap();
-                    match db.remove(&key_clone) {
-                        None => {
-                            eprintln!("The key to be removed is not found in the database: {}", key_clone);
-                        }
-                        Some(x) => {
-                            println!("Removed the Key: {:?} after expiry: {:?}", key_clone, x);
+                // The deadline lives next to the value, so a later SET of the same key
+                // simply replaces it instead of racing a timer
+                let value = RedisValue::from_string(value.clone());
+                match options.expiry {
+                    Some(ExpiryOption::KeepTtl) => db.insert_keep_ttl(key.clone(), value),
+                    Some(expiry) => {
+                        db.insert(key.clone(), value);
+                        if let Some(at) = expiry.deadline() {
+                            db.set_expiry(key, at);
                         }
                     }
-                });
-
-                // Return OK immediately
-                "+OK\r\n".to_string()
+                    None => db.insert(key.clone(), value),
+                }
+                response
             }
             Command::LPUSH(key, list) => {
                 let mut db = database.lock().unwrap();
-                let final_list = if let Some(existing_value) = db.get(key) {
-                    if let RedisValue::List(old_list) = existing_value {
+                match db.get_mut(key) {
+                    Some(RedisValue::List(existing)) => {
                         // LPUSH prepends elements one by one from left to right
-                        // So we need to reverse the new elements and prepend them
-                        let mut final_list = list.clone();
-                        final_list.reverse(); // Reverse the new elements
-                        final_list.extend_from_slice(old_list);
-                        final_list
-                    } else {
-                        // Key exists but is not a list - error in Redis
-                        return "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_string();
+                        for item in list {
+                            existing.push_front(item.clone());
+                        }
+                        reply::integer(existing.len() as i64)
                     }
-                } else {
-                    // Key doesn't exist, create new list with reversed elements
-                    let mut final_list = list.clone();
-                    final_list.reverse();
-                    final_list
-                };
-
-                db.insert(key.clone(), RedisValue::from_list(final_list.clone()));
-                format!(":{}\r\n", final_list.len())
+                    // Key exists but is not a list - error in Redis
+                    Some(_) => reply::wrong_type(),
+                    None => {
+                        let new_list: VecDeque<Bytes> = list.iter().rev().cloned().collect();
+                        let len = new_list.len();
+                        db.insert(key.clone(), RedisValue::List(new_list));
+                        // The length reported is the one right after the push, before any
+                        // blocked client takes elements away
+                        serve_blocked_clients(&mut db, key);
+                        reply::integer(len as i64)
+                    }
+                }
             }
             Command::RPUSH(key, list) => {
                 let mut db = database.lock().unwrap();
-                let final_list = if let Some(existing_value) = db.get(key) {
-                    if let RedisValue::List(old_list) = existing_value {
-                        // RPUSH appends to the end
-                        let mut final_list = old_list.clone();
-                        final_list.extend_from_slice(list);
-                        final_list
-                    } else {
-                        // Key exists but is not a list - error in Redis
-                        return "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_string();
+                match db.get_mut(key) {
+                    Some(RedisValue::List(existing)) => {
+                        existing.extend(list.iter().cloned());
+                        reply::integer(existing.len() as i64)
                     }
-                } else {
-                    // Key doesn't exist, create new list
-                    list.clone()
-                };
-
-                db.insert(key.clone(), RedisValue::from_list(final_list.clone()));
-                format!(":{}\r\n", final_list.len())
+                    Some(_) => reply::wrong_type(),
+                    None => {
+                        let len = list.len();
+                        db.insert(key.clone(), RedisValue::from_list(list.clone()));
+                        serve_blocked_clients(&mut db, key);
+                        reply::integer(len as i64)
+                    }
+                }
             }
             Command::XADD(key, entry_id, field_pairs) => {
                 let key = key.clone();
@@ -344,7 +922,7 @@ impl Command {
                             );
                             match StreamEntry::validate_entry_id(&mut new_entry, last_entry) {
                                 None => {
-                                    return "-ERR The ID specified in XADD must be greater than 0-0\r\n".to_string();
+                                    return reply::error("ERR The ID specified in XADD must be greater than 0-0");
                                 }
                                 Some(result) => {
                                     match result {
@@ -354,13 +932,15 @@ impl Command {
                                             entries.push(new_entry.clone());
                                         }
                                         false => {
-                                            return "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n".to_string();
+                                            return reply::error(
+                                                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                                            );
                                         }
                                     }
                                 }
                             }
                         } else {
-                            return "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_string();
+                            return reply::wrong_type();
                         }
                     }
                     None => {
@@ -372,157 +952,1928 @@ impl Command {
                     new_entry.milliseconds_time,
                     new_entry.sequence_number
                 );
-                format!("${}\r\n{}\r\n", entry_id.len(), entry_id)
+                reply::bulk(entry_id.as_bytes())
             }
             Command::LRANGE(key, start, end) => {
-                let db = database.lock().unwrap();
-                if let Some(msg) = db.get(key) {
-                    // let slice =
-                    if let RedisValue::List(list) = msg {
-                        let slice = lrange_slice_vec(list, *start, *end);
-                        let slice = RedisValue::from_list(slice);
-                        let response = slice.get_response();
-                        response
-                    } else {
-                        eprintln!("Error Fetching value from the database");
-                        "*0\r\n".to_string()
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::List(list)) => {
+                        match range_bounds(list.len(), *start, *end) {
+                            Some(range) => reply::bulk_array(list.range(range)),
+                            None => reply::array(vec![]),
+                        }
                     }
-                } else {
-                    "*0\r\n".to_string()
+                    Some(_) => reply::wrong_type(),
+                    None => reply::array(vec![]),
                 }
             }
             Command::GET(key) => {
-                let db = database.lock().unwrap();
-                if let Some(msg) = db.get(key) {
-                    let response = msg.get_response();
-                    response
-                } else {
-                    "$-1\r\n".to_string()
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::String(value)) => reply::bulk(value),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::null_bulk(),
                 }
             }
             Command::TYPE(key) => {
-                let db = database.lock().unwrap();
+                let mut db = database.lock().unwrap();
                 if let Some(msg) = db.get(key) {
-                    let response = msg.get_type_response();
-                    response
+                    msg.get_type_response()
                 } else {
-                    "+none\r\n".to_string()
+                    reply::simple("none")
                 }
             }
             Command::LLEN(key) => {
-                let db = database.lock().unwrap();
-                if let Some(msg) = db.get(key) {
-                    if let RedisValue::List(list) = msg {
-                        format!(":{}\r\n", list.len())
-                    } else {
-                        ":0\r\n".to_string()
-                    }
-                } else {
-                    ":0\r\n".to_string()
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::List(list)) => reply::integer(list.len() as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
                 }
             }
-            Command::LPOP(key, to_remove) => {
+            Command::LPOP(key, count) | Command::RPOP(key, count) => {
+                let end = if matches!(self, Command::LPOP(..)) { ListEnd::Left } else { ListEnd::Right };
                 let mut db = database.lock().unwrap();
-                if let Some(msg) = db.get(key) {
-                    if let RedisValue::List(list) = msg {
-                        // format!(":{}\r\n", list.len())
-                        let mut popped_list = list.clone();
-                        let mut response = "".to_string();
-                        if let Some(index) = *to_remove {
-                            if index > (popped_list.len() as isize) {
-                                eprintln!("Error: Index exceeds the mentioned value");
-                                return "$-1\r\n".to_string();
-                            }
-                            let mut response_list: Vec<String> = vec![];
-                            for i in 0..index {
-                                let popped_element = popped_list.remove(0);
-                                response_list.push(popped_element);
-                            }
-                            response = RedisValue::from_list(response_list).get_response();
-                        } else {
-                            let popped_element = popped_list.remove(0);
-                            response = RedisValue::from_string(popped_element).get_response();
-                        }
-                        db.insert(key.clone(), RedisValue::from_list(popped_list.clone()));
-                        response
-                    } else {
-                        "$-1\r\n".to_string()
+                let list = match db.get_mut(key) {
+                    Some(RedisValue::List(list)) => list,
+                    Some(_) => {
+                        return reply::wrong_type();
                     }
-                } else {
-                    "$-1\r\n".to_string()
-                }
-            }
-            Command::BLPOP(key, timeout_duration) => {
-                let db_clone = Arc::clone(database);
-                let key_clone = key.clone();
-                let timeout = *timeout_duration;
-
-                // First check if there's already an element available
-                {
-                    let mut db = db_clone.lock().unwrap();
-                    if let Some(redis_value) = db.get_mut(&key_clone) {
-                        if let RedisValue::List(list) = redis_value {
-                            if !list.is_empty() {
-                                let popped_element = list.remove(0);
-                                // Return array with key name and popped element
-                                return format!(
-                                    "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                                    key_clone.len(),
-                                    key_clone,
-                                    popped_element.len(),
-                                    popped_element
-                                );
-                            }
+                    None if count.is_some() => {
+                        return reply::null_array();
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                };
+                let response = match count {
+                    Some(count) => reply::bulk_array(end.pop_many(list, *count)),
+                    None => {
+                        match end.pop(list) {
+                            Some(popped_element) => reply::bulk(&popped_element),
+                            None => reply::null_bulk(),
+                        }
+                    }
+                };
+                db.remove_if_empty(key);
+                response
+            }
+            Command::LMPOP(keys, end, count) | Command::BLMPOP(keys, end, count, _) => {
+                let (id, receiver, timeout) = {
+                    let mut db = database.lock().unwrap();
+                    // The first key in argument order holding a non-empty list is popped from
+                    for key in keys {
+                        match db.get_mut(key) {
+                            Some(RedisValue::List(list)) if !list.is_empty() => {
+                                let popped = end.pop_many(list, *count);
+                                db.remove_if_empty(key);
+                                return multi_pop_reply(key, popped);
+                            }
+                            Some(RedisValue::List(_)) | None => {}
+                            Some(_) => {
+                                return reply::wrong_type();
+                            }
+                        }
+                    }
+                    let Command::BLMPOP(_, _, _, timeout) = self else {
+                        return reply::null_array();
+                    };
+                    let op = BlockedOp::MultiPop { end: *end, count: *count };
+                    let (id, receiver) = db.blocked.block(keys.clone(), op);
+                    (id, receiver, *timeout)
+                };
+                wait_until_served(database, id, receiver, timeout).await
+            }
+            Command::LMOVE(source, destination, from, to) => {
+                let mut db = database.lock().unwrap();
+                match list_move(&mut db, source, destination, *from, *to) {
+                    Ok(Some(item)) => {
+                        serve_blocked_clients(&mut db, destination);
+                        reply::bulk(&item)
+                    }
+                    Ok(None) => reply::null_bulk(),
+                    Err(err) => err,
+                }
+            }
+            Command::BLMOVE(source, destination, from, to, timeout) => {
+                let (id, receiver) = {
+                    let mut db = database.lock().unwrap();
+                    match list_move(&mut db, source, destination, *from, *to) {
+                        Ok(Some(item)) => {
+                            serve_blocked_clients(&mut db, destination);
+                            return reply::bulk(&item);
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            return err;
+                        }
+                    }
+                    let op = BlockedOp::Move { destination: destination.clone(), from: *from, to: *to };
+                    db.blocked.block(vec![source.clone()], op)
+                };
+                wait_until_served(database, id, receiver, *timeout).await
+            }
+            Command::LPUSHX(key, items) | Command::RPUSHX(key, items) => {
+                let end = if matches!(self, Command::LPUSHX(..)) { ListEnd::Left } else { ListEnd::Right };
+                let mut db = database.lock().unwrap();
+                match db.get_mut(key) {
+                    Some(RedisValue::List(list)) => {
+                        for item in items {
+                            end.push(list, item.clone());
+                        }
+                        reply::integer(list.len() as i64)
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::LINDEX(key, index) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::List(list)) => {
+                        match list_index(list.len(), *index).and_then(|index| list.get(index)) {
+                            Some(item) => reply::bulk(item),
+                            None => reply::null_bulk(),
+                        }
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::null_bulk(),
+                }
+            }
+            Command::LSET(key, index, element) => {
+                let mut db = database.lock().unwrap();
+                match db.get_mut(key) {
+                    Some(RedisValue::List(list)) => {
+                        match list_index(list.len(), *index).and_then(|index| list.get_mut(index)) {
+                            Some(item) => {
+                                *item = element.clone();
+                                reply::ok()
+                            }
+                            None => reply::error("ERR index out of range"),
+                        }
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::error("ERR no such key"),
+                }
+            }
+            Command::LINSERT(key, before, pivot, element) => {
+                let mut db = database.lock().unwrap();
+                match db.get_mut(key) {
+                    Some(RedisValue::List(list)) => {
+                        match list.iter().position(|item| item == pivot) {
+                            Some(position) => {
+                                let at = if *before { position } else { position + 1 };
+                                list.insert(at, element.clone());
+                                reply::integer(list.len() as i64)
+                            }
+                            None => reply::integer(-1),
+                        }
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::LREM(key, count, element) => {
+                let mut db = database.lock().unwrap();
+                let list = match db.get_mut(key) {
+                    Some(RedisValue::List(list)) => list,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::integer(0);
+                    }
+                };
+                // count > 0 removes from the head, count < 0 from the tail, 0 removes every match
+                let limit = if *count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+                let mut removed = 0;
+                if *count >= 0 {
+                    list.retain(|item| {
+                        if removed < limit && item == element {
+                            removed += 1;
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                } else {
+                    let mut kept = VecDeque::with_capacity(list.len());
+                    while let Some(item) = list.pop_back() {
+                        if removed < limit && item == element {
+                            removed += 1;
+                        } else {
+                            kept.push_front(item);
+                        }
+                    }
+                    *list = kept;
+                }
+                db.remove_if_empty(key);
+                reply::integer(removed as i64)
+            }
+            Command::LTRIM(key, start, stop) => {
+                let mut db = database.lock().unwrap();
+                let list = match db.get_mut(key) {
+                    Some(RedisValue::List(list)) => list,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::ok();
+                    }
+                };
+                match range_bounds(list.len(), *start, *stop) {
+                    Some(range) => {
+                        list.truncate(range.end);
+                        list.drain(..range.start);
+                    }
+                    None => list.clear(),
+                }
+                db.remove_if_empty(key);
+                reply::ok()
+            }
+            Command::LPOS(key, element, options) => {
+                let mut db = database.lock().unwrap();
+                let list = match db.get(key) {
+                    Some(RedisValue::List(list)) => list,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None if options.count.is_some() => {
+                        return reply::array(vec![]);
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                };
+                // A negative rank scans from the tail; |rank| - 1 matches are skipped first
+                let scan_len = if options.maxlen == 0 {
+                    list.len()
+                } else {
+                    options.maxlen.min(list.len())
+                };
+                let positions: Box<dyn Iterator<Item = usize>> = if options.rank > 0 {
+                    Box::new(0..scan_len)
+                } else {
+                    Box::new((list.len() - scan_len..list.len()).rev())
+                };
+                let wanted = match options.count {
+                    Some(0) => usize::MAX,
+                    Some(count) => count,
+                    None => 1,
+                };
+                let matches: Vec<usize> = positions
+                    .filter(|position| list[*position] == element)
+                    .skip((options.rank.unsigned_abs() - 1) as usize)
+                    .take(wanted)
+                    .collect();
+                match options.count {
+                    Some(_) => {
+                        reply::array(
+                            matches
+                                .into_iter()
+                                .map(|position| reply::integer(position as i64))
+                                .collect()
+                        )
+                    }
+                    None => {
+                        match matches.first() {
+                            Some(position) => reply::integer(*position as i64),
+                            None => reply::null_bulk(),
+                        }
+                    }
+                }
+            }
+            Command::BLPOP(keys, timeout) | Command::BRPOP(keys, timeout) => {
+                let end = if matches!(self, Command::BLPOP(..)) { ListEnd::Left } else { ListEnd::Right };
+                let (id, receiver) = {
+                    let mut db = database.lock().unwrap();
+                    // First check the keys in order for an element that is already available
+                    for key in keys {
+                        match db.get_mut(key) {
+                            Some(RedisValue::List(list)) => {
+                                if let Some(popped_element) = end.pop(list) {
+                                    db.remove_if_empty(key);
+                                    // Return array with key name and popped element
+                                    return reply::bulk_array([key, &popped_element]);
+                                }
+                            }
+                            Some(_) => {
+                                return reply::wrong_type();
+                            }
+                            None => {}
+                        }
+                    }
+                    // Otherwise wait in line; a push to any of the keys hands us the element directly
+                    db.blocked.block(keys.clone(), BlockedOp::Pop(end))
+                };
+                wait_until_served(database, id, receiver, *timeout).await
+            }
+            Command::EXPIRE(key, amount, unit, condition) => {
+                let now = now_ms() as i64;
+                let Some(deadline) = unit.deadline(*amount, now) else {
+                    return reply::error(
+                        &format!("ERR invalid expire time in '{}' command", unit.command_name())
+                    );
+                };
+
+                let mut db = database.lock().unwrap();
+                if !db.contains_key(key) {
+                    return reply::integer(0);
+                }
+                let current = db.expiry(key).map(|at| at as i64);
+                if !condition.allows(current, deadline) {
+                    return reply::integer(0);
+                }
+                if deadline <= now {
+                    db.remove(key);
+                } else {
+                    db.set_expiry(key, deadline as u64);
+                }
+                reply::integer(1)
+            }
+            Command::TTL(key) | Command::PTTL(key) => {
+                let mut db = database.lock().unwrap();
+                if !db.contains_key(key) {
+                    return reply::integer(-2);
+                }
+                match db.expiry(key) {
+                    None => reply::integer(-1),
+                    Some(at) => {
+                        let remaining = at.saturating_sub(now_ms()) as i64;
+                        if matches!(self, Command::TTL(_)) {
+                            reply::integer((remaining + 500) / 1000)
+                        } else {
+                            reply::integer(remaining)
+                        }
+                    }
+                }
+            }
+            Command::EXPIRETIME(key) | Command::PEXPIRETIME(key) => {
+                let mut db = database.lock().unwrap();
+                if !db.contains_key(key) {
+                    return reply::integer(-2);
+                }
+                match db.expiry(key) {
+                    None => reply::integer(-1),
+                    Some(at) if matches!(self, Command::EXPIRETIME(_)) => {
+                        reply::integer((at / 1000) as i64)
+                    }
+                    Some(at) => reply::integer(at as i64),
+                }
+            }
+            Command::PERSIST(key) => {
+                let mut db = database.lock().unwrap();
+                reply::integer(db.persist(key) as i64)
+            }
+            Command::INCRBY(key, increment) => {
+                let mut db = database.lock().unwrap();
+                let current = match db.get(key) {
+                    Some(RedisValue::String(value)) => {
+                        match parse_i64_strict(value) {
+                            Some(current) => current,
+                            None => {
+                                return reply::error("ERR value is not an integer or out of range");
+                            }
+                        }
+                    }
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => 0,
+                };
+                let Some(result) = current.checked_add(*increment) else {
+                    return reply::error("ERR increment or decrement would overflow");
+                };
+                // Counters keep whatever deadline the key already had
+                db.insert_keep_ttl(key.clone(), RedisValue::from_string(Bytes::from(result.to_string())));
+                reply::integer(result)
+            }
+            Command::INCRBYFLOAT(key, increment) => {
+                let mut db = database.lock().unwrap();
+                let current = match db.get(key) {
+                    Some(RedisValue::String(value)) => {
+                        match parse_f64_strict(value) {
+                            Some(current) => current,
+                            None => {
+                                return reply::error("ERR value is not a valid float");
+                            }
+                        }
+                    }
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => 0.0,
+                };
+                let result = current + increment;
+                if !result.is_finite() {
+                    return reply::error("ERR increment would produce NaN or Infinity");
+                }
+                let result = Bytes::from(format_float(result));
+                db.insert_keep_ttl(key.clone(), RedisValue::from_string(result.clone()));
+                reply::bulk(&result)
+            }
+            Command::APPEND(key, suffix) => {
+                let mut db = database.lock().unwrap();
+                match db.get_mut(key) {
+                    Some(RedisValue::String(value)) => {
+                        if value.len() + suffix.len() > MAX_STRING_LEN {
+                            return reply::error(
+                                "ERR string exceeds maximum allowed size (proto-max-bulk-len)"
+                            );
+                        }
+                        // As in SETBIT, taking the bytes out lets the buffer grow in place
+                        // instead of being copied on every append
+                        let mut data = Vec::from(std::mem::take(value));
+                        data.extend_from_slice(suffix);
+                        let len = data.len();
+                        *value = Bytes::from(data);
+                        reply::integer(len as i64)
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => {
+                        db.insert(key.clone(), RedisValue::from_string(suffix.clone()));
+                        reply::integer(suffix.len() as i64)
+                    }
+                }
+            }
+            Command::STRLEN(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::String(value)) => reply::integer(value.len() as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::GETRANGE(key, start, end) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::String(value)) => {
+                        match range_bounds(value.len(), *start, *end) {
+                            Some(range) => reply::bulk(&value[range]),
+                            None => reply::bulk(b""),
+                        }
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::bulk(b""),
+                }
+            }
+            Command::SETRANGE(key, offset, patch) => {
+                let mut db = database.lock().unwrap();
+                let current_len = match db.get(key) {
+                    Some(RedisValue::String(value)) => value.len(),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => 0,
+                };
+                // An empty patch never creates or grows the key
+                if patch.is_empty() {
+                    return reply::integer(current_len as i64);
+                }
+                if offset.saturating_add(patch.len()) > MAX_STRING_LEN {
+                    return reply::error(
+                        "ERR string exceeds maximum allowed size (proto-max-bulk-len)"
+                    );
+                }
+                if !db.contains_key(key) {
+                    db.insert(key.clone(), RedisValue::from_string(Bytes::new()));
+                }
+                let Some(RedisValue::String(value)) = db.get_mut(key) else {
+                    return reply::wrong_type();
+                };
+                // Patched in place like APPEND; bytes between the old end of the string and
+                // `offset` are zero-padded
+                let mut data = Vec::from(std::mem::take(value));
+                let end = offset + patch.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[*offset..end].copy_from_slice(patch);
+                let len = data.len();
+                *value = Bytes::from(data);
+                reply::integer(len as i64)
+            }
+            Command::GETDEL(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::String(_)) => {}
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                }
+                match db.remove(key) {
+                    Some(RedisValue::String(value)) => reply::bulk(&value),
+                    _ => reply::null_bulk(),
+                }
+            }
+            Command::GETEX(key, expiry) => {
+                let mut db = database.lock().unwrap();
+                let response = match db.get(key) {
+                    Some(RedisValue::String(value)) => reply::bulk(value),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                };
+                match expiry {
+                    Some(ExpiryOption::Persist) => {
+                        db.persist(key);
+                    }
+                    Some(expiry) => {
+                        if let Some(at) = expiry.deadline() {
+                            db.set_expiry(key, at);
+                        }
+                    }
+                    None => {}
+                }
+                response
+            }
+            Command::GETSET(key, value) => {
+                let mut db = database.lock().unwrap();
+                let response = match db.get(key) {
+                    Some(RedisValue::String(old)) => reply::bulk(old),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => reply::null_bulk(),
+                };
+                db.insert(key.clone(), RedisValue::from_string(value.clone()));
+                response
+            }
+            Command::MGET(keys) => {
+                // One lock acquisition for the whole batch keeps the read consistent
+                let mut db = database.lock().unwrap();
+                reply::array(
+                    keys
+                        .iter()
+                        .map(|key| {
+                            match db.get(key) {
+                                Some(RedisValue::String(value)) => reply::bulk(value),
+                                _ => reply::null_bulk(),
+                            }
+                        })
+                        .collect()
+                )
+            }
+            Command::MSET(pairs) => {
+                let mut db = database.lock().unwrap();
+                for (key, value) in pairs {
+                    db.insert(key.clone(), RedisValue::from_string(value.clone()));
+                }
+                reply::ok()
+            }
+            Command::MSETNX(pairs) => {
+                let mut db = database.lock().unwrap();
+                // All or nothing: a single existing key aborts the whole batch
+                if pairs.iter().any(|(key, _)| db.contains_key(key)) {
+                    return reply::integer(0);
+                }
+                for (key, value) in pairs {
+                    db.insert(key.clone(), RedisValue::from_string(value.clone()));
+                }
+                reply::integer(1)
+            }
+            Command::SETBIT(key, offset, on) => {
+                let mut db = database.lock().unwrap();
+                match db.get_mut(key) {
+                    Some(RedisValue::String(value)) => {
+                        // Taking the bytes out lets the buffer be reused instead of copied
+                        // when nothing else references it
+                        let mut data = Vec::from(std::mem::take(value));
+                        let old = bitmap::set_bit(&mut data, *offset, *on);
+                        *value = Bytes::from(data);
+                        reply::integer(old as i64)
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => {
+                        let mut data = Vec::new();
+                        bitmap::set_bit(&mut data, *offset, *on);
+                        db.insert(key.clone(), RedisValue::from_string(Bytes::from(data)));
+                        reply::integer(0)
+                    }
+                }
+            }
+            Command::GETBIT(key, offset) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::String(value)) => {
+                        reply::integer(bitmap::get_bit(value, *offset) as i64)
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::BITCOUNT(key, range) => {
+                let mut db = database.lock().unwrap();
+                let value = match db.get(key) {
+                    Some(RedisValue::String(value)) => value,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::integer(0);
+                    }
+                };
+                let bits = match range {
+                    None => Some(0..value.len() * 8),
+                    Some((start, end, unit)) => bit_range(value.len(), *start, *end, *unit),
+                };
+                match bits {
+                    Some(bits) => reply::integer(bitmap::count_bits(value, bits) as i64),
+                    None => reply::integer(0),
+                }
+            }
+            Command::BITPOS(key, bit, start, end, unit) => {
+                let mut db = database.lock().unwrap();
+                let value = match db.get(key) {
+                    Some(RedisValue::String(value)) => value,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    // A missing key is an empty string: no set bits, and clear bits from offset 0
+                    None => {
+                        return reply::integer(if *bit { -1 } else { 0 });
+                    }
+                };
+                let Some(bits) = bit_range(
+                    value.len(),
+                    start.unwrap_or(0),
+                    end.unwrap_or(-1),
+                    *unit
+                ) else {
+                    return reply::integer(-1);
+                };
+                match bitmap::find_bit(value, *bit, bits.clone()) {
+                    Some(pos) => reply::integer(pos as i64),
+                    // Without an explicit end the string counts as padded with zeros on the
+                    // right, so the first clear bit is the one just past the range
+                    None if !*bit && end.is_none() => reply::integer(bits.end as i64),
+                    None => reply::integer(-1),
+                }
+            }
+            Command::BITOP(op, destination, keys) => {
+                let mut db = database.lock().unwrap();
+                let mut sources: Vec<Bytes> = Vec::with_capacity(keys.len());
+                for key in keys {
+                    match db.get(key) {
+                        Some(RedisValue::String(value)) => sources.push(value.clone()),
+                        Some(_) => {
+                            return reply::wrong_type();
+                        }
+                        None => sources.push(Bytes::new()),
+                    }
+                }
+                let sources: Vec<&[u8]> = sources
+                    .iter()
+                    .map(|source| &source[..])
+                    .collect();
+                let result = bitmap::bit_op(*op, &sources);
+                let len = result.len();
+                if result.is_empty() {
+                    db.remove(destination);
+                } else {
+                    db.insert(destination.clone(), RedisValue::from_string(Bytes::from(result)));
+                }
+                reply::integer(len as i64)
+            }
+            Command::BITFIELD(key, ops) => {
+                let writes = ops
+                    .iter()
+                    .any(|op| matches!(op, BitFieldOp::Set(..) | BitFieldOp::IncrBy(..)));
+                let mut db = database.lock().unwrap();
+                let (existed, mut data) = match db.get_mut(key) {
+                    Some(RedisValue::String(value)) => (true, Vec::from(std::mem::take(value))),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => (false, Vec::new()),
+                };
+                let results = bitmap::bit_field(&mut data, ops);
+                if existed {
+                    db.insert_keep_ttl(key.clone(), RedisValue::from_string(Bytes::from(data)));
+                } else if writes {
+                    db.insert(key.clone(), RedisValue::from_string(Bytes::from(data)));
+                }
+                reply::array(
+                    results
+                        .into_iter()
+                        .map(|result| result.map_or_else(reply::null_bulk, reply::integer))
+                        .collect()
+                )
+            }
+            Command::HSET(key, pairs) | Command::HMSET(key, pairs) => {
+                let mut db = database.lock().unwrap();
+                let hash = match hash_for_write(&mut db, key) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let mut added = 0;
+                for (field, value) in pairs {
+                    if hash.insert(field.clone(), value.clone()).is_none() {
+                        added += 1;
+                    }
+                }
+                // Overwriting a field drops its deadline, as SET does for keys
+                for (field, _) in pairs {
+                    db.persist_field(key, field);
+                }
+                // HMSET is the deprecated form that replies OK instead of the count
+                if matches!(self, Command::HSET(..)) { reply::integer(added) } else { reply::ok() }
+            }
+            Command::HSETNX(key, field, value) => {
+                let mut db = database.lock().unwrap();
+                let hash = match hash_for_write(&mut db, key) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                if hash.contains_key(field) {
+                    return reply::integer(0);
+                }
+                hash.insert(field.clone(), value.clone());
+                reply::integer(1)
+            }
+            Command::HGET(key, field) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => {
+                        hash.get(field).map_or_else(reply::null_bulk, |value| reply::bulk(value))
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::null_bulk(),
+                }
+            }
+            Command::HMGET(key, fields) => {
+                let mut db = database.lock().unwrap();
+                let hash = match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => Some(hash),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => None,
+                };
+                reply::array(
+                    fields
+                        .iter()
+                        .map(|field| {
+                            match hash.and_then(|hash| hash.get(field)) {
+                                Some(value) => reply::bulk(value),
+                                None => reply::null_bulk(),
+                            }
+                        })
+                        .collect()
+                )
+            }
+            Command::HDEL(key, fields) => {
+                let mut db = database.lock().unwrap();
+                if !matches!(db.get(key), Some(RedisValue::Hash(_)) | None) {
+                    return reply::wrong_type();
+                }
+                let removed = fields
+                    .iter()
+                    .filter(|field| db.remove_field(key, field))
+                    .count();
+                reply::integer(removed as i64)
+            }
+            Command::HGETALL(key) | Command::HKEYS(key) | Command::HVALS(key) => {
+                let mut db = database.lock().unwrap();
+                let hash = match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => hash,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::array(vec![]);
+                    }
+                };
+                match self {
+                    Command::HKEYS(_) => reply::bulk_array(hash.keys()),
+                    Command::HVALS(_) => reply::bulk_array(hash.values()),
+                    _ => reply::bulk_array(hash.iter().flat_map(|(field, value)| [field, value])),
+                }
+            }
+            Command::HEXISTS(key, field) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => reply::integer(hash.contains_key(field) as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::HLEN(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => reply::integer(hash.len() as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::HSTRLEN(key, field) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => {
+                        reply::integer(hash.get(field).map_or(0, |value| value.len() as i64))
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::HINCRBY(key, field, increment) => {
+                let mut db = database.lock().unwrap();
+                let hash = match hash_for_write(&mut db, key) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let current = match hash.get(field) {
+                    Some(value) => {
+                        match parse_i64_strict(value) {
+                            Some(current) => current,
+                            None => {
+                                return reply::error("ERR hash value is not an integer");
+                            }
+                        }
+                    }
+                    None => 0,
+                };
+                let Some(result) = current.checked_add(*increment) else {
+                    return reply::error("ERR increment or decrement would overflow");
+                };
+                hash.insert(field.clone(), Bytes::from(result.to_string()));
+                reply::integer(result)
+            }
+            Command::HINCRBYFLOAT(key, field, increment) => {
+                let mut db = database.lock().unwrap();
+                let hash = match hash_for_write(&mut db, key) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let current = match hash.get(field) {
+                    Some(value) => {
+                        match parse_f64_strict(value) {
+                            Some(current) => current,
+                            None => {
+                                return reply::error("ERR hash value is not a float");
+                            }
+                        }
+                    }
+                    None => 0.0,
+                };
+                let result = current + increment;
+                if !result.is_finite() {
+                    return reply::error("ERR increment would produce NaN or Infinity");
+                }
+                let result = Bytes::from(format_float(result));
+                hash.insert(field.clone(), result.clone());
+                reply::bulk(&result)
+            }
+            Command::HEXPIRE(key, amount, unit, condition, fields) => {
+                let now = now_ms() as i64;
+                let Some(deadline) = unit.deadline(*amount, now) else {
+                    return reply::error(
+                        &format!("ERR invalid expire time in 'h{}' command", unit.command_name())
+                    );
+                };
+                let mut db = database.lock().unwrap();
+                if !matches!(db.get(key), Some(RedisValue::Hash(_)) | None) {
+                    return reply::wrong_type();
+                }
+                // Per field: -2 no such field, 0 condition not met, 1 deadline set, 2 deleted
+                // because the deadline is already in the past
+                let results = fields
+                    .iter()
+                    .map(|field| {
+                        if !hash_has_field(&mut db, key, field) {
+                            return reply::integer(-2);
+                        }
+                        let current = db.field_expiry(key, field).map(|at| at as i64);
+                        if !condition.allows(current, deadline) {
+                            return reply::integer(0);
                         }
+                        if deadline <= now {
+                            db.remove_field(key, field);
+                            return reply::integer(2);
+                        }
+                        db.set_field_expiry(key, field, deadline as u64);
+                        reply::integer(1)
+                    })
+                    .collect();
+                reply::array(results)
+            }
+            Command::HTTL(key, fields) |
+            Command::HPTTL(key, fields) |
+            Command::HEXPIRETIME(key, fields) |
+            Command::HPEXPIRETIME(key, fields) => {
+                let mut db = database.lock().unwrap();
+                if !matches!(db.get(key), Some(RedisValue::Hash(_)) | None) {
+                    return reply::wrong_type();
+                }
+                let results = fields
+                    .iter()
+                    .map(|field| {
+                        if !hash_has_field(&mut db, key, field) {
+                            return reply::integer(-2);
+                        }
+                        let Some(at) = db.field_expiry(key, field) else {
+                            return reply::integer(-1);
+                        };
+                        let remaining = at.saturating_sub(now_ms()) as i64;
+                        match self {
+                            Command::HTTL(..) => reply::integer((remaining + 500) / 1000),
+                            Command::HPTTL(..) => reply::integer(remaining),
+                            Command::HEXPIRETIME(..) => reply::integer((at / 1000) as i64),
+                            _ => reply::integer(at as i64),
+                        }
+                    })
+                    .collect();
+                reply::array(results)
+            }
+            Command::HPERSIST(key, fields) => {
+                let mut db = database.lock().unwrap();
+                if !matches!(db.get(key), Some(RedisValue::Hash(_)) | None) {
+                    return reply::wrong_type();
+                }
+                let results = fields
+                    .iter()
+                    .map(|field| {
+                        if !hash_has_field(&mut db, key, field) {
+                            reply::integer(-2)
+                        } else if db.persist_field(key, field) {
+                            reply::integer(1)
+                        } else {
+                            reply::integer(-1)
+                        }
+                    })
+                    .collect();
+                reply::array(results)
+            }
+            Command::HGETEX(key, expiry, fields) => {
+                let mut db = database.lock().unwrap();
+                let values: Vec<Option<Bytes>> = match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => {
+                        fields
+                            .iter()
+                            .map(|field| hash.get(field).cloned())
+                            .collect()
+                    }
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => vec![None; fields.len()],
+                };
+                for (field, value) in fields.iter().zip(&values) {
+                    if value.is_none() {
+                        continue;
+                    }
+                    match expiry.map(|expiry| (expiry, expiry.deadline())) {
+                        Some((ExpiryOption::Persist, _)) => {
+                            db.persist_field(key, field);
+                        }
+                        Some((_, Some(at))) if at <= now_ms() => {
+                            db.remove_field(key, field);
+                        }
+                        Some((_, Some(at))) => {
+                            db.set_field_expiry(key, field, at);
+                        }
+                        _ => {}
+                    }
+                }
+                reply::array(
+                    values
+                        .into_iter()
+                        .map(|value| value.map_or_else(reply::null_bulk, |value| reply::bulk(&value)))
+                        .collect()
+                )
+            }
+            Command::HRANDFIELD(key, count, with_values) => {
+                let mut db = database.lock().unwrap();
+                let hash = match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => hash,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None if count.is_some() => {
+                        return reply::array(vec![]);
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                };
+                let picked: Box<dyn Iterator<Item = (&Bytes, &Bytes)>> = match count {
+                    None => {
+                        let (field, _) = hash.entry_at(random::index(hash.len()));
+                        return reply::bulk(field);
+                    }
+                    // A negative count may return the same field several times
+                    Some(count) if *count < 0 => {
+                        Box::new(
+                            (0..count.unsigned_abs()).map(|_| hash.entry_at(random::index(hash.len())))
+                        )
+                    }
+                    Some(count) => Box::new(hash.random_entries(*count as usize).into_iter()),
+                };
+                if *with_values {
+                    reply::bulk_array(picked.flat_map(|(field, value)| [field, value]))
+                } else {
+                    reply::bulk_array(picked.map(|(field, _)| field))
+                }
+            }
+            Command::HSCAN(key, cursor, options) => {
+                let mut db = database.lock().unwrap();
+                let hash = match db.get(key) {
+                    Some(RedisValue::Hash(hash)) => hash,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return scan_reply(0, vec![]);
+                    }
+                };
+                let (next, pairs) = hash.scan(*cursor, options.count);
+                // MATCH filters what this step found, so a step may come back empty
+                let pairs = pairs
+                    .into_iter()
+                    .filter(|(field, _)| {
+                        options.pattern.as_ref().is_none_or(|pattern| scan::glob_match(pattern, field))
+                    });
+                let items = if options.novalues {
+                    pairs.map(|(field, _)| reply::bulk(field)).collect()
+                } else {
+                    pairs.flat_map(|(field, value)| [reply::bulk(field), reply::bulk(value)]).collect()
+                };
+                scan_reply(next, items)
+            }
+            Command::SADD(key, members) => {
+                let mut db = database.lock().unwrap();
+                let set = match db.get_mut(key) {
+                    Some(RedisValue::Set(set)) => set,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        let set: Set = members.iter().cloned().collect();
+                        let added = set.len();
+                        db.insert(key.clone(), RedisValue::Set(set));
+                        return reply::integer(added as i64);
+                    }
+                };
+                let added = members
+                    .iter()
+                    .filter(|member| set.insert((*member).clone()))
+                    .count();
+                reply::integer(added as i64)
+            }
+            Command::SREM(key, members) => {
+                let mut db = database.lock().unwrap();
+                let removed = match db.get_mut(key) {
+                    Some(RedisValue::Set(set)) => {
+                        members
+                            .iter()
+                            .filter(|member| set.remove(member))
+                            .count()
+                    }
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => 0,
+                };
+                db.remove_if_empty(key);
+                reply::integer(removed as i64)
+            }
+            Command::SMEMBERS(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Set(set)) => reply::bulk_array(set.iter()),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::array(vec![]),
+                }
+            }
+            Command::SISMEMBER(key, member) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Set(set)) => reply::integer(set.contains(member) as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::SMISMEMBER(key, members) => {
+                let mut db = database.lock().unwrap();
+                let set = match db.get(key) {
+                    Some(RedisValue::Set(set)) => Some(set),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => None,
+                };
+                reply::array(
+                    members
+                        .iter()
+                        .map(|member| {
+                            reply::integer(set.is_some_and(|set| set.contains(member)) as i64)
+                        })
+                        .collect()
+                )
+            }
+            Command::SCARD(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::Set(set)) => reply::integer(set.len() as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::SPOP(key, count) => {
+                let mut db = database.lock().unwrap();
+                let set = match db.get_mut(key) {
+                    Some(RedisValue::Set(set)) => set,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None if count.is_some() => {
+                        return reply::array(vec![]);
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                };
+                let members = set.random_members(count.unwrap_or(1));
+                for member in &members {
+                    set.remove(member);
+                }
+                db.remove_if_empty(key);
+                match count {
+                    Some(_) => reply::bulk_array(members),
+                    None => reply::bulk(&members[0]),
+                }
+            }
+            Command::SRANDMEMBER(key, count) => {
+                let mut db = database.lock().unwrap();
+                let set = match db.get(key) {
+                    Some(RedisValue::Set(set)) => set,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None if count.is_some() => {
+                        return reply::array(vec![]);
+                    }
+                    None => {
+                        return reply::null_bulk();
+                    }
+                };
+                match count {
+                    None => reply::bulk(&set.member_at(random::index(set.len()))),
+                    // A negative count may return the same member several times
+                    Some(count) if *count < 0 => {
+                        reply::bulk_array(
+                            (0..count.unsigned_abs()).map(|_| set.member_at(random::index(set.len())))
+                        )
+                    }
+                    Some(count) => reply::bulk_array(set.random_members(*count as usize)),
+                }
+            }
+            Command::SMOVE(source, destination, member) => {
+                let mut db = database.lock().unwrap();
+                let present = match db.get(source) {
+                    Some(RedisValue::Set(set)) => set.contains(member),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => false,
+                };
+                if !matches!(db.get(destination), Some(RedisValue::Set(_)) | None) {
+                    return reply::wrong_type();
+                }
+                if !present {
+                    return reply::integer(0);
+                }
+                if source == destination {
+                    return reply::integer(1);
+                }
+                if let Some(RedisValue::Set(set)) = db.get_mut(source) {
+                    set.remove(member);
+                }
+                db.remove_if_empty(source);
+                match db.get_mut(destination) {
+                    Some(RedisValue::Set(set)) => {
+                        set.insert(member.clone());
+                    }
+                    _ => db.insert(destination.clone(), RedisValue::Set(Set::from_iter([member.clone()]))),
+                }
+                reply::integer(1)
+            }
+            Command::SINTER(keys) | Command::SUNION(keys) | Command::SDIFF(keys) => {
+                let op = match self {
+                    Command::SINTER(_) => SetOperation::Inter,
+                    Command::SUNION(_) => SetOperation::Union,
+                    _ => SetOperation::Diff,
+                };
+                let mut db = database.lock().unwrap();
+                match sets_at(&mut db, keys) {
+                    Ok(sets) => reply::bulk_array(combine_sets(&sets, op)),
+                    Err(err) => err,
+                }
+            }
+            Command::SINTERSTORE(destination, keys) |
+            Command::SUNIONSTORE(destination, keys) |
+            Command::SDIFFSTORE(destination, keys) => {
+                let op = match self {
+                    Command::SINTERSTORE(..) => SetOperation::Inter,
+                    Command::SUNIONSTORE(..) => SetOperation::Union,
+                    _ => SetOperation::Diff,
+                };
+                let mut db = database.lock().unwrap();
+                let result: Set = match sets_at(&mut db, keys) {
+                    Ok(sets) => combine_sets(&sets, op).into_iter().collect(),
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let len = result.len();
+                // The destination is overwritten whatever it held, and deleted for an empty result
+                if result.is_empty() {
+                    db.remove(destination);
+                } else {
+                    db.insert(destination.clone(), RedisValue::Set(result));
+                }
+                reply::integer(len as i64)
+            }
+            Command::SINTERCARD(keys, limit) => {
+                let mut db = database.lock().unwrap();
+                let sets = match sets_at(&mut db, keys) {
+                    Ok(sets) => sets,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let limit = if *limit == 0 { usize::MAX } else { *limit };
+                reply::integer(intersection(&sets).take(limit).count() as i64)
+            }
+            Command::OBJECTENCODING(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(value) => reply::bulk(value.encoding().as_bytes()),
+                    None => reply::null_bulk(),
+                }
+            }
+            Command::ZADD(key, options, pairs) => {
+                let mut db = database.lock().unwrap();
+                let zset = match zset_for_write(&mut db, key) {
+                    Ok(zset) => zset,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let mut added = 0;
+                let mut changed = 0;
+                // Score of the last member touched, which is what INCR replies with
+                let mut result = None;
+                for (score, member) in pairs {
+                    let current = zset.score(member);
+                    let new_score = match current {
+                        Some(_) if options.nx => {
+                            continue;
+                        }
+                        None if options.xx => {
+                            continue;
+                        }
+                        Some(current) if options.incr => current + score,
+                        _ => *score,
+                    };
+                    if new_score.is_nan() {
+                        db.remove_if_empty(key);
+                        return reply::error("ERR resulting score is not a number (NaN)");
+                    }
+                    // GT / LT only restrict updates; new members are always added
+                    if let Some(current) = current {
+                        if (options.gt && new_score <= current) || (options.lt && new_score >= current) {
+                            continue;
+                        }
+                        if new_score != current {
+                            changed += 1;
+                        }
+                    } else {
+                        added += 1;
+                    }
+                    zset.insert(member.clone(), new_score);
+                    result = Some(new_score);
+                }
+                // Every pair may have been skipped, which must not leave an empty key behind
+                db.remove_if_empty(key);
+                if options.incr {
+                    result.map_or_else(reply::null_bulk, |score| reply::bulk(format_float(score).as_bytes()))
+                } else if options.ch {
+                    reply::integer(added + changed)
+                } else {
+                    reply::integer(added)
+                }
+            }
+            Command::ZINCRBY(key, increment, member) => {
+                let mut db = database.lock().unwrap();
+                let zset = match zset_for_write(&mut db, key) {
+                    Ok(zset) => zset,
+                    Err(err) => {
+                        return err;
+                    }
+                };
+                let score = zset.score(member).unwrap_or(0.0) + increment;
+                if score.is_nan() {
+                    db.remove_if_empty(key);
+                    return reply::error("ERR resulting score is not a number (NaN)");
+                }
+                zset.insert(member.clone(), score);
+                reply::bulk(format_float(score).as_bytes())
+            }
+            Command::ZREM(key, members) => {
+                let mut db = database.lock().unwrap();
+                let removed = match db.get_mut(key) {
+                    Some(RedisValue::ZSet(zset)) => {
+                        members
+                            .iter()
+                            .filter(|member| zset.remove(member))
+                            .count()
+                    }
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => 0,
+                };
+                db.remove_if_empty(key);
+                reply::integer(removed as i64)
+            }
+            Command::ZSCORE(key, member) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::ZSet(zset)) => {
+                        match zset.score(member) {
+                            Some(score) => reply::bulk(format_float(score).as_bytes()),
+                            None => reply::null_bulk(),
+                        }
+                    }
+                    Some(_) => reply::wrong_type(),
+                    None => reply::null_bulk(),
+                }
+            }
+            Command::ZMSCORE(key, members) => {
+                let mut db = database.lock().unwrap();
+                let zset = match db.get(key) {
+                    Some(RedisValue::ZSet(zset)) => Some(zset),
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => None,
+                };
+                reply::array(
+                    members
+                        .iter()
+                        .map(|member| {
+                            match zset.and_then(|zset| zset.score(member)) {
+                                Some(score) => reply::bulk(format_float(score).as_bytes()),
+                                None => reply::null_bulk(),
+                            }
+                        })
+                        .collect()
+                )
+            }
+            Command::ZCARD(key) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::ZSet(zset)) => reply::integer(zset.len() as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::ZRANK(key, member, with_score) | Command::ZREVRANK(key, member, with_score) => {
+                let mut db = database.lock().unwrap();
+                let missing = if *with_score { reply::null_array() } else { reply::null_bulk() };
+                let zset = match db.get(key) {
+                    Some(RedisValue::ZSet(zset)) => zset,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return missing;
+                    }
+                };
+                let (Some(rank), Some(score)) = (zset.rank(member), zset.score(member)) else {
+                    return missing;
+                };
+                let rank = if matches!(self, Command::ZRANK(..)) { rank } else { zset.len() - 1 - rank };
+                if *with_score {
+                    reply::array(vec![reply::integer(rank as i64), reply::bulk(format_float(score).as_bytes())])
+                } else {
+                    reply::integer(rank as i64)
+                }
+            }
+            Command::ZCOUNT(key, range) => {
+                let mut db = database.lock().unwrap();
+                match db.get(key) {
+                    Some(RedisValue::ZSet(zset)) => reply::integer(zset.count_in_range(range) as i64),
+                    Some(_) => reply::wrong_type(),
+                    None => reply::integer(0),
+                }
+            }
+            Command::ZRANGE(key, spec, with_scores) => {
+                let mut db = database.lock().unwrap();
+                let zset = match db.get(key) {
+                    Some(RedisValue::ZSet(zset)) => zset,
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {
+                        return reply::array(vec![]);
+                    }
+                };
+                scored_reply(zrange(zset, spec), *with_scores)
+            }
+            Command::ZRANGESTORE(destination, source, spec) => {
+                let mut db = database.lock().unwrap();
+                let mut result = ZSet::default();
+                match db.get(source) {
+                    Some(RedisValue::ZSet(zset)) => {
+                        for (member, score) in zrange(zset, spec) {
+                            result.insert(member.clone(), score);
+                        }
+                    }
+                    Some(_) => {
+                        return reply::wrong_type();
+                    }
+                    None => {}
+                }
+                let len = result.len();
+                // As with SINTERSTORE, the destination is overwritten or deleted for an empty result
+                if result.is_empty() {
+                    db.remove(destination);
+                } else {
+                    db.insert(destination.clone(), RedisValue::ZSet(result));
+                }
+                reply::integer(len as i64)
+            }
+            Command::ZUNION(spec, with_scores) |
+            Command::ZINTER(spec, with_scores) |
+            Command::ZDIFF(spec, with_scores) => {
+                let op = match self {
+                    Command::ZUNION(..) => SetOperation::Union,
+                    Command::ZINTER(..) => SetOperation::Inter,
+                    _ => SetOperation::Diff,
+                };
+                let mut db = database.lock().unwrap();
+                match scored_sets_at(&mut db, &spec.keys) {
+                    Ok(inputs) => {
+                        let result = combine_zsets(&inputs, spec, op);
+                        scored_reply(result.iter().collect(), *with_scores)
+                    }
+                    Err(err) => err,
+                }
+            }
+            Command::ZUNIONSTORE(destination, spec) |
+            Command::ZINTERSTORE(destination, spec) |
+            Command::ZDIFFSTORE(destination, spec) => {
+                let op = match self {
+                    Command::ZUNIONSTORE(..) => SetOperation::Union,
+                    Command::ZINTERSTORE(..) => SetOperation::Inter,
+                    _ => SetOperation::Diff,
+                };
+                let mut db = database.lock().unwrap();
+                let result = match scored_sets_at(&mut db, &spec.keys) {
+                    Ok(inputs) => combine_zsets(&inputs, spec, op),
+                    Err(err) => {
+                        return err;
                     }
+                };
+                let len = result.len();
+                if result.is_empty() {
+                    db.remove(destination);
+                } else {
+                    db.insert(destination.clone(), RedisValue::ZSet(result));
                 }
+                reply::integer(len as i64)
+            }
+            Command::ERROR(msg) => reply::error(msg),
+            Command::UNKNOWN => reply::error("ERR unknown command"),
+        }
+    }
+}
+
+// Resolves a possibly negative list index, None when it falls outside the list
+fn list_index(len: usize, index: isize) -> Option<usize> {
+    let index = if index < 0 { (len as isize) + index } else { index };
+    (index >= 0 && (index as usize) < len).then_some(index as usize)
+}
+
+/// Waits for a client registered with `BlockingState::block` to be served, giving up after
+/// `timeout` (None waits forever). Returns the reply to send to the client.
+async fn wait_until_served(
+    database: &Arc<Mutex<Database>>,
+    id: ClientId,
+    mut receiver: oneshot::Receiver<Vec<u8>>,
+    timeout: Option<Duration>
+) -> Vec<u8> {
+    let mut guard = WaitGuard { database, id: Some(id) };
+    let served = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, &mut receiver).await.ok(),
+        None => Some((&mut receiver).await),
+    };
+    guard.id = None;
+    match served {
+        Some(Ok(response)) => response,
+        _ => {
+            let mut db = database.lock().unwrap();
+            if db.blocked.unblock(id) {
+                return reply::null_array(); // Timeout reached
+            }
+            // Served while the timeout fired: the reply is already in the channel
+            receiver.try_recv().unwrap_or_else(|_| reply::null_array())
+        }
+    }
+}
+
+// Unblocks the client when waiting is abandoned halfway, which is what happens when its
+// connection closes: the command is dropped before it was served or timed out
+struct WaitGuard<'a> {
+    database: &'a Arc<Mutex<Database>>,
+    id: Option<ClientId>,
+}
 
-                // If no element available, start blocking
-                let start_time = Instant::now();
-                let mut interval = interval(Duration::from_millis(10)); // Check every 10ms
-                loop {
-                    interval.tick().await;
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            if let Ok(mut db) = self.database.lock() {
+                db.blocked.unblock(id);
+            }
+        }
+    }
+}
 
-                    // Check if timeout reached (if timeout > 0)
-                    if timeout > 0.0 {
-                        // dbg!("ENTERS HERE");
-                        let elapsed = start_time.elapsed().as_secs() as f64;
-                        dbg!(elapsed);
-                        if elapsed >= timeout {
-                            return "*-1\r\n".to_string(); // Timeout reached
+/// Hands elements of the list at `key` to clients blocked on it, longest waiting first,
+/// until either the list or the line of waiting clients runs out. Serving a BLMOVE pushes
+/// to another list, which may in turn have clients waiting, so those keys are served next.
+pub fn serve_blocked_clients(db: &mut Database, key: &Bytes) {
+    let mut ready = VecDeque::from([key.clone()]);
+    while let Some(key) = ready.pop_front() {
+        while db.blocked.has_waiters(&key) {
+            match db.get(&key) {
+                Some(RedisValue::List(list)) if !list.is_empty() => {}
+                _ => {
+                    break;
+                }
+            }
+            let Some(client) = db.blocked.pop_waiter(&key) else {
+                break;
+            };
+            let response = match &client.op {
+                BlockedOp::Pop(end) => {
+                    let popped = match db.get_mut(&key) {
+                        Some(RedisValue::List(list)) => end.pop(list),
+                        _ => None,
+                    };
+                    db.remove_if_empty(&key);
+                    match popped {
+                        Some(popped_element) => reply::bulk_array([&key, &popped_element]),
+                        None => reply::null_array(),
+                    }
+                }
+                BlockedOp::MultiPop { end, count } => {
+                    let popped = match db.get_mut(&key) {
+                        Some(RedisValue::List(list)) => end.pop_many(list, *count),
+                        _ => Vec::new(),
+                    };
+                    db.remove_if_empty(&key);
+                    multi_pop_reply(&key, popped)
+                }
+                BlockedOp::Move { destination, from, to } => {
+                    match list_move(db, &key, destination, *from, *to) {
+                        Ok(Some(item)) => {
+                            ready.push_back(destination.clone());
+                            reply::bulk(&item)
                         }
+                        Ok(None) => reply::null_array(),
+                        Err(err) => err,
                     }
+                }
+            };
+            let _ = client.sender.send(response);
+        }
+    }
+}
+
+// LMPOP / BLMPOP reply: the key popped from and the elements taken from it
+fn multi_pop_reply(key: &Bytes, popped: Vec<Bytes>) -> Vec<u8> {
+    reply::array(vec![reply::bulk(key), reply::bulk_array(popped)])
+}
+
+/// Pops an element from one end of `source` and pushes it to one end of `destination`,
+/// creating the destination list when needed. `source` and `destination` may be the same
+/// list, which rotates it. Ok(None) when the source is missing; Err carries the reply when
+/// either key holds something other than a list.
+fn list_move(
+    db: &mut Database,
+    source: &Bytes,
+    destination: &Bytes,
+    from: ListEnd,
+    to: ListEnd
+) -> Result<Option<Bytes>, Vec<u8>> {
+    match db.get(source) {
+        Some(RedisValue::List(_)) => {}
+        Some(_) => {
+            return Err(reply::wrong_type());
+        }
+        None => {
+            return Ok(None);
+        }
+    }
+    if !matches!(db.get(destination), Some(RedisValue::List(_)) | None) {
+        return Err(reply::wrong_type());
+    }
+    let item = match db.get_mut(source) {
+        Some(RedisValue::List(list)) => from.pop(list),
+        _ => None,
+    };
+    let Some(item) = item else {
+        return Ok(None);
+    };
+    // Push before dropping an emptied source: when both are the same key the item goes back
+    // into the list it came from, and the key keeps its TTL instead of being re-created
+    match db.get_mut(destination) {
+        Some(RedisValue::List(list)) => to.push(list, item.clone()),
+        _ => db.insert(destination.clone(), RedisValue::List(VecDeque::from([item.clone()]))),
+    }
+    db.remove_if_empty(source);
+    Ok(Some(item))
+}
+
+/// The hash stored at `key` for a command that writes fields, creating an empty one when the
+/// key is missing. Err carries the WRONGTYPE reply when the key holds something else.
+///
+/// A hash created here is empty until the caller adds a field, so callers must not bail out
+/// between this and the write without going through `remove_if_empty`.
+fn hash_for_write<'a>(
+    db: &'a mut Database,
+    key: &Bytes
+) -> Result<&'a mut Hash, Vec<u8>> {
+    if !db.contains_key(key) {
+        db.insert(key.clone(), RedisValue::Hash(Hash::default()));
+    }
+    match db.get_mut(key) {
+        Some(RedisValue::Hash(hash)) => Ok(hash),
+        _ => Err(reply::wrong_type()),
+    }
+}
+
+/// The sorted set stored at `key` for a command that adds members, creating an empty one when
+/// the key is missing. Err carries the WRONGTYPE reply when the key holds something else.
+///
+/// Callers must go through `remove_if_empty` afterwards, since they may end up adding nothing.
+fn zset_for_write<'a>(db: &'a mut Database, key: &Bytes) -> Result<&'a mut ZSet, Vec<u8>> {
+    if !db.contains_key(key) {
+        db.insert(key.clone(), RedisValue::ZSet(ZSet::default()));
+    }
+    match db.get_mut(key) {
+        Some(RedisValue::ZSet(zset)) => Ok(zset),
+        _ => Err(reply::wrong_type()),
+    }
+}
+
+// The members ZRANGE selects with their scores, in reply order
+fn zrange<'a>(zset: &'a ZSet, spec: &ZRangeSpec) -> Vec<(&'a Bytes, f64)> {
+    let len = zset.len();
+    // Everything is resolved to ranks in ascending order first; REV only changes the end
+    // the members are read from
+    let ranks = match &spec.by {
+        ZRangeBy::Rank(start, stop) => {
+            let Some(ranks) = range_bounds(len, *start, *stop) else {
+                return Vec::new();
+            };
+            // With REV the indexes count from the highest score
+            if spec.rev { len - ranks.end..len - ranks.start } else { ranks }
+        }
+        ZRangeBy::Score(range) => zset.score_range_ranks(range),
+        ZRangeBy::Lex(range) => zset.lex_range_ranks(range),
+    };
+    let ranks = match spec.limit {
+        None => ranks,
+        Some((offset, _)) if offset < 0 || (offset as usize) >= ranks.len() => {
+            return Vec::new();
+        }
+        Some((offset, count)) => {
+            let offset = offset as usize;
+            let available = ranks.len() - offset;
+            let count = if count < 0 { available } else { (count as usize).min(available) };
+            if spec.rev {
+                ranks.end - offset - count..ranks.end - offset
+            } else {
+                ranks.start + offset..ranks.start + offset + count
+            }
+        }
+    };
+    zset.slice(ranks, spec.rev)
+}
+
+// Members with their scores as ZRANGE and friends reply them, scores interleaved on WITHSCORES
+fn scored_reply(members: Vec<(&Bytes, f64)>, with_scores: bool) -> Vec<u8> {
+    if with_scores {
+        reply::array(
+            members
+                .into_iter()
+                .flat_map(|(member, score)| {
+                    [reply::bulk(member), reply::bulk(format_float(score).as_bytes())]
+                })
+                .collect()
+        )
+    } else {
+        reply::bulk_array(members.into_iter().map(|(member, _)| member))
+    }
+}
+
+// An input of ZUNION and friends, which may be a plain set whose members all score 1
+#[derive(Clone, Copy)]
+enum ScoredSet<'a> {
+    Set(&'a Set),
+    ZSet(&'a ZSet),
+}
+
+impl<'a> ScoredSet<'a> {
+    fn len(&self) -> usize {
+        match self {
+            ScoredSet::Set(set) => set.len(),
+            ScoredSet::ZSet(zset) => zset.len(),
+        }
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            ScoredSet::Set(set) => set.contains(member).then_some(1.0),
+            ScoredSet::ZSet(zset) => zset.score(member),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, f64)> + 'a> {
+        match *self {
+            ScoredSet::Set(set) => Box::new(set.iter().map(|member| (member, 1.0))),
+            ScoredSet::ZSet(zset) => Box::new(zset.iter().map(|(member, score)| (member.clone(), score))),
+        }
+    }
+}
+
+// The inputs stored at `keys` for ZUNION and friends, None for missing keys.
+// Err carries the WRONGTYPE reply when one of the keys is neither a set nor a sorted set.
+fn scored_sets_at<'a>(
+    db: &'a mut Database,
+    keys: &[Bytes]
+) -> Result<Vec<Option<ScoredSet<'a>>>, Vec<u8>> {
+    db.get_many(keys)
+        .into_iter()
+        .map(|value| {
+            match value {
+                Some(RedisValue::Set(set)) => Ok(Some(ScoredSet::Set(set))),
+                Some(RedisValue::ZSet(zset)) => Ok(Some(ScoredSet::ZSet(zset))),
+                Some(_) => Err(reply::wrong_type()),
+                None => Ok(None),
+            }
+        })
+        .collect()
+}
 
-                    // Check if element is now available
-                    let mut db = db_clone.lock().unwrap();
-                    if let Some(redis_value) = db.get_mut(&key_clone) {
-                        if let RedisValue::List(list) = redis_value {
-                            if !list.is_empty() {
-                                let popped_element = list.remove(0);
-                                // Return array with key name and popped element
-                                return format!(
-                                    "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                                    key_clone.len(),
-                                    key_clone,
-                                    popped_element.len(),
-                                    popped_element
-                                );
-                            }
-                        }
-                    }
-                    // Drop the lock to allow other operations to modify the list
-                    drop(db);
+// Missing keys behave as empty sets. Scores are multiplied by the weight of their input, with
+// 0 * inf counting as 0 like in Redis; ZDIFF keeps the scores of the first input as they are.
+fn combine_zsets(inputs: &[Option<ScoredSet>], spec: &ZCombineSpec, op: SetOperation) -> ZSet {
+    let weighted = |idx: usize, score: f64| {
+        let score = score * spec.weights[idx];
+        if score.is_nan() { 0.0 } else { score }
+    };
+    let mut result = ZSet::default();
+    match op {
+        SetOperation::Union => {
+            let mut scores: HashMap<Bytes, f64> = HashMap::new();
+            for (idx, input) in inputs.iter().enumerate() {
+                for (member, score) in input.iter().flat_map(|input| input.iter()) {
+                    let score = weighted(idx, score);
+                    scores
+                        .entry(member)
+                        .and_modify(|acc| {
+                            *acc = spec.aggregate.apply(*acc, score);
+                        })
+                        .or_insert(score);
+                }
+            }
+            for (member, score) in scores {
+                result.insert(member, score);
+            }
+        }
+        SetOperation::Inter => {
+            // A missing key makes the whole intersection empty
+            let Some(inputs) = inputs.iter().copied().collect::<Option<Vec<ScoredSet>>>() else {
+                return result;
+            };
+            // Walk the smallest input, looking members up in the others
+            let mut order: Vec<usize> = (0..inputs.len()).collect();
+            order.sort_by_key(|idx| inputs[*idx].len());
+            let Some((smallest, others)) = order.split_first() else {
+                return result;
+            };
+            'members: for (member, score) in inputs[*smallest].iter() {
+                let mut acc = weighted(*smallest, score);
+                for idx in others {
+                    let Some(score) = inputs[*idx].score(&member) else {
+                        continue 'members;
+                    };
+                    acc = spec.aggregate.apply(acc, weighted(*idx, score));
+                }
+                result.insert(member, acc);
+            }
+        }
+        SetOperation::Diff => {
+            let Some((Some(first), others)) = inputs.split_first() else {
+                return result;
+            };
+            for (member, score) in first.iter() {
+                if !others.iter().flatten().any(|input| input.score(&member).is_some()) {
+                    result.insert(member, score);
                 }
             }
-            Command::UNKNOWN => "-ERR unknown command\r\n".to_string(),
         }
     }
+    result
+}
+
+// The sets stored at `keys` for the set algebra commands, None for missing keys.
+// Err carries the WRONGTYPE reply when one of the keys holds something else.
+fn sets_at<'a>(
+    db: &'a mut Database,
+    keys: &[Bytes]
+) -> Result<Vec<Option<&'a Set>>, Vec<u8>> {
+    db.get_many(keys)
+        .into_iter()
+        .map(|value| {
+            match value {
+                Some(RedisValue::Set(set)) => Ok(Some(set)),
+                Some(_) => Err(reply::wrong_type()),
+                None => Ok(None),
+            }
+        })
+        .collect()
+}
+
+// Missing keys behave as empty sets
+fn combine_sets(sets: &[Option<&Set>], op: SetOperation) -> HashSet<Bytes> {
+    match op {
+        SetOperation::Inter => intersection(sets).collect(),
+        SetOperation::Union => sets.iter().flatten().flat_map(|set| set.iter()).collect(),
+        SetOperation::Diff => {
+            let Some((Some(first), others)) = sets.split_first() else {
+                return HashSet::new();
+            };
+            first
+                .iter()
+                .filter(|member| !others.iter().flatten().any(|set| set.contains(member)))
+                .collect()
+        }
+    }
+}
+
+// Members common to all `sets`, found by walking the smallest one. Lazy, so SINTERCARD can
+// stop as soon as it reaches its LIMIT.
+fn intersection<'a>(sets: &[Option<&'a Set>]) -> impl Iterator<Item = Bytes> + 'a {
+    // A missing key makes the whole intersection empty
+    let mut sets: Vec<&Set> = sets
+        .iter()
+        .copied()
+        .collect::<Option<_>>()
+        .unwrap_or_default();
+    sets.sort_by_key(|set| set.len());
+    let smallest = (!sets.is_empty()).then(|| sets.remove(0));
+    smallest
+        .into_iter()
+        .flat_map(|set| set.iter())
+        .filter(move |member| sets.iter().all(|set| set.contains(member)))
+}
+
+// The reply of the SCAN family: the cursor to continue from and the items of this step
+fn scan_reply(cursor: u64, items: Vec<Vec<u8>>) -> Vec<u8> {
+    reply::array(vec![reply::bulk(cursor.to_string().as_bytes()), reply::array(items)])
 }
 
-pub fn lrange_slice_vec(list: &Vec<String>, start: isize, stop: isize) -> Vec<String> {
-    let len = list.len() as isize;
+// Whether the hash at `key` has `field`, after dropping fields that are past their deadline
+fn hash_has_field(db: &mut Database, key: &Bytes, field: &Bytes) -> bool {
+    matches!(db.get(key), Some(RedisValue::Hash(hash)) if hash.contains_key(field))
+}
+
+/// Resolves inclusive LRANGE style `start` / `stop` indices, where negative values count
+/// from the end, into the positions they cover. None when the range selects nothing.
+pub fn range_bounds(len: usize, start: isize, stop: isize) -> Option<Range<usize>> {
+    let len = len as isize;
     if len == 0 {
-        return Vec::new();
+        return None;
     }
 
     // Convert negative indices to positive (counting from end)
@@ -538,12 +2889,860 @@ pub fn lrange_slice_vec(list: &Vec<String>, start: isize, stop: isize) -> Vec<St
     }
 
     if actual_start > actual_stop || actual_start >= len {
-        return Vec::new();
+        return None;
+    }
+
+    // Inclusive end index, exclusive for Rust ranges
+    Some(actual_start as usize..(actual_stop + 1) as usize)
+}
+
+// Request arguments arrive as bulk strings; anything else means a malformed request
+fn bulk_bytes(value: Value) -> Option<Bytes> {
+    match value {
+        Value::BufBulk(data) => Some(Bytes::from(data)),
+        Value::Bulk(data) => Some(Bytes::from(data)),
+        _ => None,
+    }
+}
+
+fn wrong_arity(cmd: &str) -> Command {
+    Command::ERROR(format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()))
+}
+
+fn not_an_integer() -> Command {
+    Command::ERROR("ERR value is not an integer or out of range".to_string())
+}
+
+// Blocking timeouts are seconds given as a float; zero means wait forever
+fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, Command> {
+    match parse_arg::<f64>(arg) {
+        Some(timeout) if timeout < 0.0 => {
+            Err(Command::ERROR("ERR timeout is negative".to_string()))
+        }
+        Some(0.0) => Ok(None),
+        Some(timeout) if timeout.is_finite() => {
+            Duration::try_from_secs_f64(timeout)
+                .map(Some)
+                .map_err(|_| Command::ERROR("ERR timeout is out of range".to_string()))
+        }
+        _ => Err(Command::ERROR("ERR timeout is not a float or out of range".to_string())),
+    }
+}
+
+fn syntax_error() -> Command {
+    Command::ERROR("ERR syntax error".to_string())
+}
+
+// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+// PXAT unix-time-milliseconds | KEEPTTL]
+fn parse_set(arr: &[Bytes]) -> Command {
+    if arr.len() < 3 {
+        return wrong_arity("set");
+    }
+    let mut options = SetOptions::default();
+    let mut idx = 3;
+    while idx < arr.len() {
+        let option = String::from_utf8_lossy(&arr[idx]).to_uppercase();
+        match option.as_str() {
+            "NX" if !options.xx => {
+                options.nx = true;
+            }
+            "XX" if !options.nx => {
+                options.xx = true;
+            }
+            "GET" => {
+                options.get = true;
+            }
+            "KEEPTTL" if options.expiry.is_none() => {
+                options.expiry = Some(ExpiryOption::KeepTtl);
+            }
+            "EX" | "PX" | "EXAT" | "PXAT" if options.expiry.is_none() && idx + 1 < arr.len() => {
+                match parse_expiry_option(&option, &arr[idx + 1], "set") {
+                    Ok(expiry) => {
+                        options.expiry = Some(expiry);
+                    }
+                    Err(err) => {
+                        return err;
+                    }
+                }
+                idx += 1;
+            }
+            _ => {
+                return syntax_error();
+            }
+        }
+        idx += 1;
+    }
+    Command::SET(arr[1].clone(), arr[2].clone(), options)
+}
+
+// The amount following EX / PX / EXAT / PXAT, which must be a positive integer
+fn parse_expiry_option(unit: &str, amount: &[u8], cmd: &str) -> Result<ExpiryOption, Command> {
+    let Some(amount) = parse_arg::<i64>(amount) else {
+        return Err(not_an_integer());
+    };
+    let expiry = if amount <= 0 {
+        None
+    } else {
+        let amount = amount as u64;
+        match unit {
+            "EX" => amount.checked_mul(1000).map(ExpiryOption::After),
+            "PX" => Some(ExpiryOption::After(amount)),
+            "EXAT" => amount.checked_mul(1000).map(ExpiryOption::At),
+            _ => Some(ExpiryOption::At(amount)),
+        }
+    };
+    expiry.ok_or_else(|| {
+        Command::ERROR(format!("ERR invalid expire time in '{}' command", cmd))
+    })
+}
+
+fn parse_bit_offset(arg: &[u8]) -> Option<usize> {
+    parse_arg::<u64>(arg)
+        .filter(|offset| *offset <= bitmap::MAX_BIT_OFFSET)
+        .map(|offset| offset as usize)
+}
+
+fn parse_bit_unit(arg: &[u8]) -> Option<BitUnit> {
+    match String::from_utf8_lossy(arg).to_uppercase().as_str() {
+        "BYTE" => Some(BitUnit::Byte),
+        "BIT" => Some(BitUnit::Bit),
+        _ => None,
+    }
+}
+
+// BITCOUNT key [start end [BYTE | BIT]]
+fn parse_bitcount(arr: &[Bytes]) -> Command {
+    match arr.len() {
+        2 => Command::BITCOUNT(arr[1].clone(), None),
+        4 | 5 => {
+            let unit = match arr.get(4) {
+                Some(unit) => {
+                    match parse_bit_unit(unit) {
+                        Some(unit) => unit,
+                        None => {
+                            return syntax_error();
+                        }
+                    }
+                }
+                None => BitUnit::Byte,
+            };
+            match (parse_arg(&arr[2]), parse_arg(&arr[3])) {
+                (Some(start), Some(end)) => {
+                    Command::BITCOUNT(arr[1].clone(), Some((start, end, unit)))
+                }
+                _ => not_an_integer(),
+            }
+        }
+        _ => syntax_error(),
+    }
+}
+
+// BITPOS key bit [start [end [BYTE | BIT]]]
+fn parse_bitpos(arr: &[Bytes]) -> Command {
+    if arr.len() > 6 {
+        return syntax_error();
+    }
+    let bit = match &arr[2][..] {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+            return Command::ERROR("ERR The bit argument must be 1 or 0.".to_string());
+        }
+    };
+    let mut bounds = [None, None];
+    for (bound, arg) in bounds.iter_mut().zip(arr.iter().skip(3)) {
+        match parse_arg(arg) {
+            Some(value) => {
+                *bound = Some(value);
+            }
+            None => {
+                return not_an_integer();
+            }
+        }
+    }
+    let unit = match arr.get(5) {
+        Some(unit) => {
+            match parse_bit_unit(unit) {
+                Some(unit) => unit,
+                None => {
+                    return syntax_error();
+                }
+            }
+        }
+        None => BitUnit::Byte,
+    };
+    Command::BITPOS(arr[1].clone(), bit, bounds[0], bounds[1], unit)
+}
+
+fn parse_list_end(arg: &[u8]) -> Option<ListEnd> {
+    match String::from_utf8_lossy(arg).to_uppercase().as_str() {
+        "LEFT" => Some(ListEnd::Left),
+        "RIGHT" => Some(ListEnd::Right),
+        _ => None,
+    }
+}
+
+// The part of LMPOP / BLMPOP after the timeout: numkeys key [key ...] LEFT | RIGHT [COUNT count]
+fn parse_lmpop(args: &[Bytes], timeout: Option<Option<Duration>>) -> Command {
+    let numkeys = match parse_arg::<i64>(&args[0]) {
+        Some(numkeys) if numkeys > 0 => numkeys as usize,
+        Some(_) => {
+            return Command::ERROR("ERR numkeys should be greater than 0".to_string());
+        }
+        None => {
+            return not_an_integer();
+        }
+    };
+    // numkeys, the keys and the direction must all be there
+    if numkeys + 2 > args.len() {
+        return syntax_error();
+    }
+    let keys = args[1..=numkeys].to_vec();
+    let Some(end) = parse_list_end(&args[numkeys + 1]) else {
+        return syntax_error();
+    };
+    let count = match &args[numkeys + 2..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+            match parse_arg::<i64>(count) {
+                Some(count) if count > 0 => count as usize,
+                Some(_) => {
+                    return Command::ERROR("ERR count should be greater than 0".to_string());
+                }
+                None => {
+                    return not_an_integer();
+                }
+            }
+        }
+        _ => {
+            return syntax_error();
+        }
+    };
+    match timeout {
+        Some(timeout) => Command::BLMPOP(keys, end, count, timeout),
+        None => Command::LMPOP(keys, end, count),
+    }
+}
+
+// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+fn parse_lpos(arr: &[Bytes]) -> Command {
+    let mut options = LposOptions { rank: 1, count: None, maxlen: 0 };
+    let mut idx = 3;
+    while idx < arr.len() {
+        let option = String::from_utf8_lossy(&arr[idx]).to_uppercase();
+        let Some(value) = arr.get(idx + 1) else {
+            return syntax_error();
+        };
+        if !matches!(option.as_str(), "RANK" | "COUNT" | "MAXLEN") {
+            return syntax_error();
+        }
+        let Some(value) = parse_arg::<i64>(value) else {
+            return not_an_integer();
+        };
+        match option.as_str() {
+            "RANK" => {
+                if value == 0 || value == i64::MIN {
+                    return Command::ERROR(
+                        "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the last match".to_string()
+                    );
+                }
+                options.rank = value;
+            }
+            "COUNT" if value < 0 => {
+                return Command::ERROR("ERR COUNT can't be negative".to_string());
+            }
+            "COUNT" => {
+                options.count = Some(value as usize);
+            }
+            "MAXLEN" if value < 0 => {
+                return Command::ERROR("ERR MAXLEN can't be negative".to_string());
+            }
+            "MAXLEN" => {
+                options.maxlen = value as usize;
+            }
+            _ => {
+                return syntax_error();
+            }
+        }
+        idx += 2;
+    }
+    Command::LPOS(arr[1].clone(), arr[2].clone(), options)
+}
+
+// BITFIELD key [GET type offset | [OVERFLOW WRAP | SAT | FAIL]
+// SET type offset value | INCRBY type offset increment ...]
+// BITFIELD_RO only accepts GET subcommands
+fn parse_bitfield(arr: &[Bytes], read_only: bool) -> Command {
+    let mut ops = Vec::new();
+    let mut idx = 2;
+    while idx < arr.len() {
+        let subcommand = String::from_utf8_lossy(&arr[idx]).to_uppercase();
+        if read_only && subcommand != "GET" {
+            return Command::ERROR("ERR BITFIELD_RO only supports the GET subcommand".to_string());
+        }
+        if subcommand == "OVERFLOW" {
+            let Some(mode) = arr.get(idx + 1) else {
+                return syntax_error();
+            };
+            let mode = match String::from_utf8_lossy(mode).to_uppercase().as_str() {
+                "WRAP" => BitFieldOverflow::Wrap,
+                "SAT" => BitFieldOverflow::Sat,
+                "FAIL" => BitFieldOverflow::Fail,
+                _ => {
+                    return Command::ERROR("ERR Invalid OVERFLOW type specified".to_string());
+                }
+            };
+            ops.push(BitFieldOp::Overflow(mode));
+            idx += 2;
+            continue;
+        }
+
+        let arg_count = match subcommand.as_str() {
+            "GET" => 3,
+            "SET" | "INCRBY" => 4,
+            _ => {
+                return syntax_error();
+            }
+        };
+        if idx + arg_count > arr.len() {
+            return syntax_error();
+        }
+        let Some(field) = BitFieldType::parse(&arr[idx + 1]) else {
+            return Command::ERROR(
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".to_string()
+            );
+        };
+        // "#N" addresses the N-th field of this width instead of a raw bit offset
+        let offset = match arr[idx + 2].strip_prefix(b"#") {
+            Some(index) => {
+                parse_arg::<u64>(index).and_then(|index| index.checked_mul(field.bits as u64))
+            }
+            None => parse_arg::<u64>(&arr[idx + 2]),
+        };
+        // The last bit of the field must be addressable too; offsets near u64::MAX overflow
+        let last_bit = offset.and_then(|offset| offset.checked_add((field.bits as u64) - 1));
+        let offset = match offset {
+            Some(offset) if last_bit.is_some_and(|last| last <= bitmap::MAX_BIT_OFFSET) => {
+                offset as usize
+            }
+            _ => {
+                return Command::ERROR(
+                    "ERR bit offset is not an integer or out of range".to_string()
+                );
+            }
+        };
+        let op = if arg_count == 3 {
+            BitFieldOp::Get(field, offset)
+        } else {
+            let Some(value) = parse_arg::<i64>(&arr[idx + 3]) else {
+                return not_an_integer();
+            };
+            if subcommand == "SET" {
+                BitFieldOp::Set(field, offset, value)
+            } else {
+                BitFieldOp::IncrBy(field, offset, value)
+            }
+        };
+        ops.push(op);
+        idx += arg_count;
+    }
+    Command::BITFIELD(arr[1].clone(), ops)
+}
+
+// Resolves a BITCOUNT / BITPOS range over a string of `len` bytes into bit positions
+fn bit_range(len: usize, start: isize, end: isize, unit: BitUnit) -> Option<Range<usize>> {
+    match unit {
+        BitUnit::Byte => range_bounds(len, start, end).map(|range| range.start * 8..range.end * 8),
+        BitUnit::Bit => range_bounds(len * 8, start, end),
+    }
+}
+
+// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+// PXAT unix-time-milliseconds | PERSIST]
+fn parse_getex(arr: &[Bytes]) -> Command {
+    let option = arr.get(2).map(|option| String::from_utf8_lossy(option).to_uppercase());
+    let expiry = match (option.as_deref(), arr.len()) {
+        (None, _) => None,
+        (Some("PERSIST"), 3) => Some(ExpiryOption::Persist),
+        (Some(unit @ ("EX" | "PX" | "EXAT" | "PXAT")), 4) => {
+            match parse_expiry_option(unit, &arr[3], "getex") {
+                Ok(expiry) => Some(expiry),
+                Err(err) => {
+                    return err;
+                }
+            }
+        }
+        _ => {
+            return syntax_error();
+        }
+    };
+    Command::GETEX(arr[1].clone(), expiry)
+}
+
+// EXPIRE / PEXPIRE / EXPIREAT / PEXPIREAT key amount [NX | XX | GT | LT]
+fn parse_expire(cmd: &str, arr: &[Bytes], unit: ExpireUnit) -> Command {
+    if arr.len() < 3 {
+        return wrong_arity(cmd);
+    }
+    let Some(amount) = parse_arg::<i64>(&arr[2]) else {
+        return not_an_integer();
+    };
+    match parse_expire_condition(&arr[3..]) {
+        Ok(condition) => Command::EXPIRE(arr[1].clone(), amount, unit, condition),
+        Err(err) => err,
+    }
+}
+
+// The NX / XX / GT / LT flags of the EXPIRE family, validated for conflicts
+fn parse_expire_condition(flags: &[Bytes]) -> Result<ExpireCondition, Command> {
+    let mut condition = ExpireCondition::default();
+    for flag in flags {
+        match String::from_utf8_lossy(flag).to_uppercase().as_str() {
+            "NX" => {
+                condition.nx = true;
+            }
+            "XX" => {
+                condition.xx = true;
+            }
+            "GT" => {
+                condition.gt = true;
+            }
+            "LT" => {
+                condition.lt = true;
+            }
+            other => {
+                return Err(Command::ERROR(format!("ERR Unsupported option {}", other)));
+            }
+        }
+    }
+    if condition.nx && (condition.xx || condition.gt || condition.lt) {
+        return Err(
+            Command::ERROR(
+                "ERR NX and XX, GT or LT options at the same time are not compatible".to_string()
+            )
+        );
+    }
+    if condition.gt && condition.lt {
+        return Err(
+            Command::ERROR("ERR GT and LT options at the same time are not compatible".to_string())
+        );
+    }
+    Ok(condition)
+}
+
+// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+fn parse_zadd(arr: &[Bytes]) -> Command {
+    let mut options = ZaddOptions::default();
+    let mut idx = 2;
+    while idx < arr.len() {
+        match String::from_utf8_lossy(&arr[idx]).to_uppercase().as_str() {
+            "NX" => {
+                options.nx = true;
+            }
+            "XX" => {
+                options.xx = true;
+            }
+            "GT" => {
+                options.gt = true;
+            }
+            "LT" => {
+                options.lt = true;
+            }
+            "CH" => {
+                options.ch = true;
+            }
+            "INCR" => {
+                options.incr = true;
+            }
+            _ => {
+                break;
+            }
+        }
+        idx += 1;
+    }
+    let pairs = &arr[idx..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return syntax_error();
+    }
+    if options.nx && options.xx {
+        return Command::ERROR("ERR XX and NX options at the same time are not compatible".to_string());
+    }
+    if (options.gt && options.lt) || (options.nx && (options.gt || options.lt)) {
+        return Command::ERROR(
+            "ERR GT, LT, and/or NX options at the same time are not compatible".to_string()
+        );
+    }
+    if options.incr && pairs.len() > 2 {
+        return Command::ERROR("ERR INCR option supports a single increment-element pair".to_string());
+    }
+    let mut parsed = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks(2) {
+        let Some(score) = parse_score(&pair[0]) else {
+            return Command::ERROR("ERR value is not a valid float".to_string());
+        };
+        parsed.push((score, pair[1].clone()));
+    }
+    Command::ZADD(arr[1].clone(), options, parsed)
+}
+
+// The part of ZRANGE and the legacy range commands after the key (after destination and
+// source for ZRANGESTORE): min max followed by options. Returns the selection and WITHSCORES.
+fn parse_zrange(cmd: &str, args: &[Bytes]) -> Result<(ZRangeSpec, bool), Command> {
+    #[derive(PartialEq)]
+    enum By {
+        Rank,
+        Score,
+        Lex,
+    }
+    let (mut by, mut rev) = match cmd {
+        "ZREVRANGE" => (By::Rank, true),
+        "ZRANGEBYSCORE" => (By::Score, false),
+        "ZREVRANGEBYSCORE" => (By::Score, true),
+        "ZRANGEBYLEX" => (By::Lex, false),
+        "ZREVRANGEBYLEX" => (By::Lex, true),
+        _ => (By::Rank, false),
+    };
+    // BYSCORE / BYLEX / REV only exist in the unified ZRANGE grammar
+    let unified = cmd == "ZRANGE" || cmd == "ZRANGESTORE";
+    let mut limit = None;
+    let mut with_scores = false;
+    let mut idx = 2;
+    while idx < args.len() {
+        match String::from_utf8_lossy(&args[idx]).to_uppercase().as_str() {
+            "BYSCORE" if unified && by == By::Rank => {
+                by = By::Score;
+            }
+            "BYLEX" if unified && by == By::Rank => {
+                by = By::Lex;
+            }
+            "REV" if unified => {
+                rev = true;
+            }
+            "WITHSCORES" if cmd != "ZRANGESTORE" => {
+                with_scores = true;
+            }
+            "LIMIT" if idx + 2 < args.len() => {
+                match (parse_arg::<i64>(&args[idx + 1]), parse_arg::<i64>(&args[idx + 2])) {
+                    (Some(offset), Some(count)) => {
+                        limit = Some((offset, count));
+                    }
+                    _ => {
+                        return Err(not_an_integer());
+                    }
+                }
+                idx += 2;
+            }
+            _ => {
+                return Err(syntax_error());
+            }
+        }
+        idx += 1;
+    }
+    if limit.is_some() && by == By::Rank {
+        return Err(
+            Command::ERROR(
+                "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string()
+            )
+        );
+    }
+    if with_scores && by == By::Lex {
+        return Err(
+            if unified {
+                Command::ERROR(
+                    "ERR syntax error, WITHSCORES not supported in combination with BYLEX".to_string()
+                )
+            } else {
+                syntax_error()
+            }
+        );
+    }
+
+    // Reading from the highest score down takes the bounds as max min
+    let (min, max) = if rev && by != By::Rank { (&args[1], &args[0]) } else { (&args[0], &args[1]) };
+    let by = match by {
+        By::Rank => {
+            match (parse_arg(min), parse_arg(max)) {
+                (Some(start), Some(stop)) => ZRangeBy::Rank(start, stop),
+                _ => {
+                    return Err(not_an_integer());
+                }
+            }
+        }
+        By::Score => {
+            match parse_score_range(min, max) {
+                Some(range) => ZRangeBy::Score(range),
+                None => {
+                    return Err(Command::ERROR("ERR min or max is not a float".to_string()));
+                }
+            }
+        }
+        By::Lex => {
+            match (parse_lex_bound(min), parse_lex_bound(max)) {
+                (Some(min), Some(max)) => ZRangeBy::Lex(LexRange { min, max }),
+                _ => {
+                    return Err(
+                        Command::ERROR("ERR min or max not valid string range item".to_string())
+                    );
+                }
+            }
+        }
+    };
+    Ok((ZRangeSpec { by, rev, limit }, with_scores))
+}
+
+// "-", "+", "[member" (inclusive) or "(member" (exclusive)
+fn parse_lex_bound(arg: &Bytes) -> Option<LexBound> {
+    match arg.first()? {
+        b'-' if arg.len() == 1 => Some(LexBound::NegInf),
+        b'+' if arg.len() == 1 => Some(LexBound::PosInf),
+        b'[' => Some(LexBound::Inclusive(arg.slice(1..))),
+        b'(' => Some(LexBound::Exclusive(arg.slice(1..))),
+        _ => None,
+    }
+}
+
+// A sorted set score: any float including "inf" and "-inf", but not NaN
+fn parse_score(arg: &[u8]) -> Option<f64> {
+    parse_arg::<f64>(arg).filter(|score| !score.is_nan())
+}
+
+// The min and max of ZCOUNT and friends, where a leading "(" makes a bound exclusive
+fn parse_score_range(min: &[u8], max: &[u8]) -> Option<ScoreRange> {
+    let parse_bound = |arg: &[u8]| {
+        match arg.strip_prefix(b"(") {
+            Some(bound) => parse_score(bound).map(|score| (score, true)),
+            None => parse_score(arg).map(|score| (score, false)),
+        }
+    };
+    let (min, min_exclusive) = parse_bound(min)?;
+    let (max, max_exclusive) = parse_bound(max)?;
+    Some(ScoreRange { min, min_exclusive, max, max_exclusive })
+}
+
+// The part of ZUNION / ZINTER / ZDIFF after the command name (after the destination for the
+// STORE forms): numkeys key [key ...] followed by options. Returns the inputs and WITHSCORES.
+fn parse_zcombine(cmd: &str, args: &[Bytes]) -> Result<(ZCombineSpec, bool), Command> {
+    let numkeys = match parse_arg::<i64>(&args[0]) {
+        Some(numkeys) if numkeys > 0 => numkeys as usize,
+        Some(_) => {
+            return Err(
+                Command::ERROR(
+                    format!("ERR at least 1 input key is needed for '{}' command", cmd.to_lowercase())
+                )
+            );
+        }
+        None => {
+            return Err(not_an_integer());
+        }
+    };
+    if numkeys > args.len() - 1 {
+        return Err(syntax_error());
+    }
+    let keys = args[1..1 + numkeys].to_vec();
+    let mut spec = ZCombineSpec { keys, weights: vec![1.0; numkeys], aggregate: Aggregate::Sum };
+    // ZDIFF has no scores to weigh or aggregate, and the STORE forms never reply with scores
+    let combines_scores = !cmd.starts_with("ZDIFF");
+    let stores = cmd.ends_with("STORE");
+    let mut with_scores = false;
+    let mut idx = 1 + numkeys;
+    while idx < args.len() {
+        match String::from_utf8_lossy(&args[idx]).to_uppercase().as_str() {
+            "WEIGHTS" if combines_scores && idx + numkeys < args.len() => {
+                for (weight, arg) in spec.weights.iter_mut().zip(&args[idx + 1..idx + 1 + numkeys]) {
+                    match parse_score(arg) {
+                        Some(parsed) => {
+                            *weight = parsed;
+                        }
+                        None => {
+                            return Err(Command::ERROR("ERR weight value is not a float".to_string()));
+                        }
+                    }
+                }
+                idx += numkeys;
+            }
+            "AGGREGATE" if combines_scores && idx + 1 < args.len() => {
+                spec.aggregate = match String::from_utf8_lossy(&args[idx + 1]).to_uppercase().as_str() {
+                    "SUM" => Aggregate::Sum,
+                    "MIN" => Aggregate::Min,
+                    "MAX" => Aggregate::Max,
+                    _ => {
+                        return Err(syntax_error());
+                    }
+                };
+                idx += 1;
+            }
+            "WITHSCORES" if !stores => {
+                with_scores = true;
+            }
+            _ => {
+                return Err(syntax_error());
+            }
+        }
+        idx += 1;
+    }
+    Ok((spec, with_scores))
+}
+
+// SINTERCARD numkeys key [key ...] [LIMIT limit]
+fn parse_sintercard(arr: &[Bytes]) -> Command {
+    let numkeys = match parse_arg::<i64>(&arr[1]) {
+        Some(numkeys) if numkeys > 0 => numkeys as usize,
+        Some(_) => {
+            return Command::ERROR("ERR numkeys should be greater than 0".to_string());
+        }
+        None => {
+            return not_an_integer();
+        }
+    };
+    if numkeys > arr.len() - 2 {
+        return Command::ERROR("ERR Number of keys can't be greater than number of args".to_string());
+    }
+    let keys = arr[2..2 + numkeys].to_vec();
+    let limit = match &arr[2 + numkeys..] {
+        [] => 0,
+        [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => {
+            match parse_arg::<i64>(limit) {
+                Some(limit) if limit >= 0 => limit as usize,
+                Some(_) => {
+                    return Command::ERROR("ERR LIMIT can't be negative".to_string());
+                }
+                None => {
+                    return not_an_integer();
+                }
+            }
+        }
+        _ => {
+            return syntax_error();
+        }
+    };
+    Command::SINTERCARD(keys, limit)
+}
+
+// cursor [MATCH pattern] [COUNT count], plus NOVALUES when `allow_novalues` is set
+fn parse_scan(args: &[Bytes], allow_novalues: bool) -> Result<(u64, ScanOptions), Command> {
+    let Some(cursor) = parse_arg::<u64>(&args[0]) else {
+        return Err(Command::ERROR("ERR invalid cursor".to_string()));
+    };
+    let mut options = ScanOptions { pattern: None, count: 10, novalues: false };
+    let mut idx = 1;
+    while idx < args.len() {
+        let option = String::from_utf8_lossy(&args[idx]).to_uppercase();
+        match (option.as_str(), args.get(idx + 1)) {
+            ("MATCH", Some(pattern)) => {
+                options.pattern = Some(pattern.clone());
+                idx += 2;
+            }
+            ("COUNT", Some(count)) => {
+                options.count = match parse_arg::<i64>(count) {
+                    Some(count) if count >= 1 => count as usize,
+                    Some(_) => {
+                        return Err(syntax_error());
+                    }
+                    None => {
+                        return Err(not_an_integer());
+                    }
+                };
+                idx += 2;
+            }
+            ("NOVALUES", _) if allow_novalues => {
+                options.novalues = true;
+                idx += 1;
+            }
+            _ => {
+                return Err(syntax_error());
+            }
+        }
+    }
+    Ok((cursor, options))
+}
+
+// The FIELDS numfields field [field ...] block that ends the field expiration commands
+fn parse_fields(args: &[Bytes]) -> Result<Vec<Bytes>, Command> {
+    let [keyword, numfields, fields @ ..] = args else {
+        return Err(
+            Command::ERROR(
+                "ERR Mandatory argument FIELDS is missing or not at the right position".to_string()
+            )
+        );
+    };
+    if !keyword.eq_ignore_ascii_case(b"FIELDS") {
+        return Err(
+            Command::ERROR(
+                "ERR Mandatory argument FIELDS is missing or not at the right position".to_string()
+            )
+        );
+    }
+    let numfields = match parse_arg::<i64>(numfields) {
+        Some(numfields) if numfields > 0 => numfields as usize,
+        Some(_) => {
+            return Err(
+                Command::ERROR("ERR Parameter `numFields` should be greater than 0".to_string())
+            );
+        }
+        None => {
+            return Err(not_an_integer());
+        }
+    };
+    if numfields != fields.len() {
+        return Err(
+            Command::ERROR(
+                "ERR The `numfields` parameter must match the number of arguments".to_string()
+            )
+        );
+    }
+    Ok(fields.to_vec())
+}
+
+// HEXPIRE key amount [NX | XX | GT | LT] FIELDS numfields field [field ...]
+fn parse_hexpire(cmd: &str, arr: &[Bytes], unit: ExpireUnit) -> Command {
+    if arr.len() < 6 {
+        return wrong_arity(cmd);
     }
+    let Some(amount) = parse_arg::<i64>(&arr[2]) else {
+        return not_an_integer();
+    };
+    // The condition flag is optional, so FIELDS starts either right after the amount or one later
+    let (condition, rest) = if arr[3].eq_ignore_ascii_case(b"FIELDS") {
+        (Ok(ExpireCondition::default()), &arr[3..])
+    } else {
+        (parse_expire_condition(&arr[3..4]), &arr[4..])
+    };
+    let condition = match condition {
+        Ok(condition) => condition,
+        Err(err) => {
+            return err;
+        }
+    };
+    match parse_fields(rest) {
+        Ok(fields) => Command::HEXPIRE(arr[1].clone(), amount, unit, condition, fields),
+        Err(err) => err,
+    }
+}
 
-    // Inclusive end index, exclusive for Rust slice
-    let st = actual_start as usize;
-    let en = (actual_stop + 1) as usize;
-    list[st..en.min(list.len())].to_vec()
+// HGETEX key [EX seconds | PX milliseconds | EXAT unix-time | PXAT unix-time-ms | PERSIST]
+// FIELDS numfields field [field ...]
+fn parse_hgetex(arr: &[Bytes]) -> Command {
+    let option = String::from_utf8_lossy(&arr[2]).to_uppercase();
+    let (expiry, rest) = match option.as_str() {
+        "PERSIST" => (Some(ExpiryOption::Persist), &arr[3..]),
+        unit @ ("EX" | "PX" | "EXAT" | "PXAT") => {
+            match parse_expiry_option(unit, &arr[3], "hgetex") {
+                Ok(expiry) => (Some(expiry), &arr[4..]),
+                Err(err) => {
+                    return err;
+                }
+            }
+        }
+        _ => (None, &arr[2..]),
+    };
+    match parse_fields(rest) {
+        Ok(fields) => Command::HGETEX(arr[1].clone(), expiry, fields),
+        Err(err) => err,
+    }
 }
 
+// Parses a numeric argument, which must be valid ASCII text
+fn parse_arg<T: FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
@@ -0,0 +1,265 @@
+use std::collections::{ BTreeSet, HashMap };
+use std::time::{ SystemTime, UNIX_EPOCH };
+use bytes::Bytes;
+use crate::blocking::BlockingState;
+use crate::value::RedisValue;
+
+// Upper bound on keys reclaimed per active expiry tick so the lock is never held for long
+const ACTIVE_EXPIRE_MAX_KEYS: usize = 200;
+
+/// Current Unix time in milliseconds, the unit every deadline is stored in
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The keyspace shared by every connection.
+///
+/// Expiry is kept as metadata next to the values rather than in timers: `expires` maps a
+/// key to its absolute deadline (Unix ms) and `deadlines` orders the same pairs by time so
+/// the active expiry cycle can find due keys without scanning. Keys are dropped lazily
+/// whenever they are looked up past their deadline, and actively by `active_expire_cycle`.
+///
+/// Hash fields can carry their own deadlines (HEXPIRE and friends), kept per hash in
+/// `field_expires` and across all hashes in `field_deadlines`. A hash whose last field
+/// expires is deleted.
+#[derive(Debug, Default)]
+pub struct Database {
+    data: HashMap<Bytes, RedisValue>,
+    expires: HashMap<Bytes, u64>,
+    deadlines: BTreeSet<(u64, Bytes)>,
+    field_expires: HashMap<Bytes, FieldDeadlines>,
+    field_deadlines: BTreeSet<(u64, Bytes, Bytes)>,
+    // Clients waiting in BLPOP and friends for keys of this keyspace
+    pub blocked: BlockingState,
+}
+
+// The field deadlines of one hash, both by field and ordered by time so a lookup of the key
+// only has to look at the earliest one to know whether any field is due
+#[derive(Debug, Default)]
+struct FieldDeadlines {
+    by_field: HashMap<Bytes, u64>,
+    by_time: BTreeSet<(u64, Bytes)>,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database::default()
+    }
+
+    pub fn get(&mut self, key: &Bytes) -> Option<&RedisValue> {
+        self.expire_if_needed(key);
+        self.data.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &Bytes) -> Option<&mut RedisValue> {
+        self.expire_if_needed(key);
+        self.data.get_mut(key)
+    }
+
+    /// Looks up several keys at once, for commands that read many values without copying them
+    pub fn get_many(&mut self, keys: &[Bytes]) -> Vec<Option<&RedisValue>> {
+        for key in keys {
+            self.expire_if_needed(key);
+        }
+        keys.iter()
+            .map(|key| self.data.get(key))
+            .collect()
+    }
+
+    pub fn contains_key(&mut self, key: &Bytes) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Stores `value` under `key`, dropping any deadline the previous value had (plain SET semantics)
+    pub fn insert(&mut self, key: Bytes, value: RedisValue) {
+        self.clear_expiry(&key);
+        self.clear_field_expiries(&key);
+        self.data.insert(key, value);
+    }
+
+    /// Replaces the value stored under `key` but leaves its deadline untouched
+    pub fn insert_keep_ttl(&mut self, key: Bytes, value: RedisValue) {
+        self.expire_if_needed(&key);
+        self.clear_field_expiries(&key);
+        self.data.insert(key, value);
+    }
+
+    pub fn remove(&mut self, key: &Bytes) -> Option<RedisValue> {
+        self.expire_if_needed(key);
+        self.clear_expiry(key);
+        self.clear_field_expiries(key);
+        self.data.remove(key)
+    }
+
+    /// Deletes `key` if it holds a collection that has become empty
+    pub fn remove_if_empty(&mut self, key: &Bytes) {
+        if self.data.get(key).is_some_and(RedisValue::is_empty_collection) {
+            self.remove(key);
+        }
+    }
+
+    /// Sets the absolute deadline of an existing key. Returns false when the key doesn't exist.
+    pub fn set_expiry(&mut self, key: &Bytes, at_ms: u64) -> bool {
+        if !self.contains_key(key) {
+            return false;
+        }
+        self.clear_expiry(key);
+        self.expires.insert(key.clone(), at_ms);
+        self.deadlines.insert((at_ms, key.clone()));
+        true
+    }
+
+    /// Deadline of a live key in Unix ms, or None when it has no expiry (or doesn't exist)
+    pub fn expiry(&mut self, key: &Bytes) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.expires.get(key).copied()
+    }
+
+    /// Removes the deadline of a key. Returns true when there was one to remove.
+    pub fn persist(&mut self, key: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        self.clear_expiry(key)
+    }
+
+    /// Deadline of a field of the hash at `key` in Unix ms, or None when it has no expiry
+    pub fn field_expiry(&mut self, key: &Bytes, field: &Bytes) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.field_expires.get(key)?.by_field.get(field).copied()
+    }
+
+    /// Sets the absolute deadline of a field of the hash at `key`. Returns false when the
+    /// field doesn't exist.
+    pub fn set_field_expiry(&mut self, key: &Bytes, field: &Bytes, at_ms: u64) -> bool {
+        match self.get(key) {
+            Some(RedisValue::Hash(hash)) if hash.contains_key(field) => {}
+            _ => {
+                return false;
+            }
+        }
+        self.clear_field_expiry(key, field);
+        let fields = self.field_expires.entry(key.clone()).or_default();
+        fields.by_field.insert(field.clone(), at_ms);
+        fields.by_time.insert((at_ms, field.clone()));
+        self.field_deadlines.insert((at_ms, key.clone(), field.clone()));
+        true
+    }
+
+    /// Removes the deadline of a hash field. Returns true when there was one to remove.
+    pub fn persist_field(&mut self, key: &Bytes, field: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        self.clear_field_expiry(key, field)
+    }
+
+    /// Deletes a field of the hash at `key`, and the key with it when it was the last field.
+    /// Returns false when there was no such field.
+    pub fn remove_field(&mut self, key: &Bytes, field: &Bytes) -> bool {
+        self.expire_if_needed(key);
+        self.clear_field_expiry(key, field);
+        let removed = match self.data.get_mut(key) {
+            Some(RedisValue::Hash(hash)) => hash.remove(field).is_some(),
+            _ => false,
+        };
+        self.remove_if_empty(key);
+        removed
+    }
+
+    /// Reclaims keys and hash fields whose deadline has passed, oldest deadline first.
+    /// Returns how many were removed.
+    pub fn active_expire_cycle(&mut self) -> usize {
+        let now = now_ms();
+        let mut removed = 0;
+        while removed < ACTIVE_EXPIRE_MAX_KEYS {
+            match self.deadlines.first() {
+                Some((at, _)) if *at <= now => {}
+                _ => {
+                    break;
+                }
+            }
+            if let Some((_, key)) = self.deadlines.pop_first() {
+                self.expires.remove(&key);
+                self.clear_field_expiries(&key);
+                self.data.remove(&key);
+                removed += 1;
+            }
+        }
+        while removed < ACTIVE_EXPIRE_MAX_KEYS {
+            let (key, field) = match self.field_deadlines.first() {
+                Some((at, key, field)) if *at <= now => (key.clone(), field.clone()),
+                _ => {
+                    break;
+                }
+            };
+            self.remove_field(&key, &field);
+            removed += 1;
+        }
+        removed
+    }
+
+    fn expire_if_needed(&mut self, key: &Bytes) {
+        let now = now_ms();
+        if let Some(at) = self.expires.get(key) {
+            if *at <= now {
+                self.clear_expiry(key);
+                self.clear_field_expiries(key);
+                self.data.remove(key);
+                return;
+            }
+        }
+        // Fields are dropped earliest deadline first, stopping at the first one still alive,
+        // so a key with nothing due costs a single comparison
+        let mut removed_any = false;
+        loop {
+            let field = match self.field_expires.get(key).and_then(|fields| fields.by_time.first()) {
+                Some((at, field)) if *at <= now => field.clone(),
+                _ => {
+                    break;
+                }
+            };
+            self.clear_field_expiry(key, &field);
+            if let Some(RedisValue::Hash(hash)) = self.data.get_mut(key) {
+                hash.remove(&field);
+            }
+            removed_any = true;
+        }
+        if removed_any && self.data.get(key).is_some_and(RedisValue::is_empty_collection) {
+            self.clear_expiry(key);
+            self.data.remove(key);
+        }
+    }
+
+    fn clear_expiry(&mut self, key: &Bytes) -> bool {
+        match self.expires.remove(key) {
+            Some(at) => {
+                self.deadlines.remove(&(at, key.clone()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn clear_field_expiry(&mut self, key: &Bytes, field: &Bytes) -> bool {
+        let Some(fields) = self.field_expires.get_mut(key) else {
+            return false;
+        };
+        let Some(at) = fields.by_field.remove(field) else {
+            return false;
+        };
+        fields.by_time.remove(&(at, field.clone()));
+        if fields.by_field.is_empty() {
+            self.field_expires.remove(key);
+        }
+        self.field_deadlines.remove(&(at, key.clone(), field.clone()));
+        true
+    }
+
+    fn clear_field_expiries(&mut self, key: &Bytes) {
+        if let Some(fields) = self.field_expires.remove(key) {
+            for (field, at) in fields.by_field {
+                self.field_deadlines.remove(&(at, key.clone(), field));
+            }
+        }
+    }
+}
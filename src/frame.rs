@@ -0,0 +1,264 @@
+use bytes::{ Buf, BytesMut };
+use resp::Value;
+use thiserror::Error;
+
+// Same limits real Redis enforces on incoming requests
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error("Protocol error: {0}")] Invalid(String),
+}
+
+/// Parses the requests of one connection as their bytes arrive.
+///
+/// A request is an array of bulk strings. Every bulk string is taken off the buffer as soon
+/// as it is complete and the parser remembers how far into the array it got, so a request
+/// spread over many reads is still only looked at once.
+#[derive(Debug, Default)]
+pub struct FrameParser {
+    // Bulk strings of the array being read that haven't arrived yet, None between requests
+    remaining: Option<usize>,
+    items: Vec<Value>,
+    // Length of the bulk string whose header was read but whose data is still incomplete
+    bulk_len: Option<usize>,
+}
+
+impl FrameParser {
+    pub fn new() -> FrameParser {
+        FrameParser::default()
+    }
+
+    /// Tries to take one complete request off the front of `buf`.
+    ///
+    /// Returns `Ok(None)` when the rest of the request hasn't arrived yet; the caller should
+    /// read more bytes into `buf` and call again. Calling this in a loop drains every
+    /// pipelined request that has fully arrived.
+    pub fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<Value>, FrameError> {
+        let mut remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                if buf.is_empty() {
+                    return Ok(None);
+                }
+                if buf[0] != b'*' {
+                    return parse_inline(buf);
+                }
+                let Some(line) = take_line(buf, "mbulk count")? else {
+                    return Ok(None);
+                };
+                let len = parse_int(&line[1..], "multibulk length")?;
+                // Like Redis, "*0" and "*-1" are requests without any argument
+                if len <= 0 {
+                    return Ok(Some(Value::Array(Vec::new())));
+                }
+                let len = len as usize;
+                if len > MAX_ARRAY_LEN {
+                    return Err(FrameError::Invalid("invalid multibulk length".to_string()));
+                }
+                // The header alone shouldn't make us allocate room for a million arguments
+                self.items = Vec::with_capacity(len.min(1024));
+                len
+            }
+        };
+
+        while remaining > 0 {
+            self.remaining = Some(remaining);
+            let len = match self.bulk_len {
+                Some(len) => len,
+                None => {
+                    let Some(line) = take_line(buf, "bulk count")? else {
+                        return Ok(None);
+                    };
+                    if line[0] != b'$' {
+                        return Err(
+                            FrameError::Invalid(format!("expected '$', got '{}'", line[0] as char))
+                        );
+                    }
+                    let len = parse_int(&line[1..], "bulk length")?;
+                    if len < 0 || (len as usize) > MAX_BULK_LEN {
+                        return Err(FrameError::Invalid("invalid bulk length".to_string()));
+                    }
+                    self.bulk_len = Some(len as usize);
+                    len as usize
+                }
+            };
+            if buf.len() < len + 2 {
+                return Ok(None);
+            }
+            if &buf[len..len + 2] != b"\r\n" {
+                return Err(FrameError::Invalid("bulk string is not terminated".to_string()));
+            }
+            let data = buf.split_to(len);
+            buf.advance(2);
+            self.items.push(Value::BufBulk(data.to_vec()));
+            self.bulk_len = None;
+            remaining -= 1;
+        }
+
+        self.remaining = None;
+        Ok(Some(Value::Array(std::mem::take(&mut self.items))))
+    }
+}
+
+// Takes the "\r\n" terminated line at the front of `buf`, without the terminator. None when
+// the line hasn't fully arrived yet.
+fn take_line(buf: &mut BytesMut, what: &str) -> Result<Option<BytesMut>, FrameError> {
+    let Some(end) = buf.windows(2).position(|w| w == b"\r\n") else {
+        if buf.len() > MAX_INLINE_LEN {
+            return Err(FrameError::Invalid(format!("too big {} string", what)));
+        }
+        return Ok(None);
+    };
+    if end == 0 {
+        return Err(FrameError::Invalid("empty line".to_string()));
+    }
+    let line = buf.split_to(end);
+    buf.advance(2);
+    Ok(Some(line))
+}
+
+fn parse_int(digits: &[u8], what: &str) -> Result<i64, FrameError> {
+    std::str
+        ::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| FrameError::Invalid(format!("invalid {}", what)))
+}
+
+// Inline commands are what redis-cli / telnet send when typed by hand: "SET key value\r\n"
+fn parse_inline(buf: &mut BytesMut) -> Result<Option<Value>, FrameError> {
+    let Some(end) = buf.iter().position(|&b| b == b'\n') else {
+        if buf.len() > MAX_INLINE_LEN {
+            return Err(FrameError::Invalid("too big inline request".to_string()));
+        }
+        return Ok(None);
+    };
+    let line = buf.split_to(end + 1);
+    let line = &line[..end];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let items = line
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| Value::BufBulk(word.to_vec()))
+        .collect();
+    Ok(Some(Value::Array(items)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(value: Value) -> Vec<Vec<u8>> {
+        match value {
+            Value::Array(items) => {
+                items
+                    .into_iter()
+                    .map(|item| {
+                        match item {
+                            Value::BufBulk(data) => data,
+                            other => panic!("expected a bulk string, got {:?}", other),
+                        }
+                    })
+                    .collect()
+            }
+            other => panic!("expected an array, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_a_complete_request() {
+        let mut parser = FrameParser::new();
+        let mut buf = BytesMut::from(&b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nv\r\nv1\r\n"[..]);
+        let request = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(args(request), vec![b"SET".to_vec(), b"k".to_vec(), b"v\r\nv1".to_vec()]);
+        assert!(buf.is_empty());
+        assert!(parser.parse(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn resumes_a_request_split_over_reads() {
+        let request = b"*2\r\n$4\r\nECHO\r\n$11\r\nhello world\r\n";
+        // Feed the request one byte at a time, as if every read returned a single byte
+        let mut parser = FrameParser::new();
+        let mut buf = BytesMut::new();
+        for (idx, byte) in request.iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            let parsed = parser.parse(&mut buf).unwrap();
+            if idx + 1 < request.len() {
+                assert!(parsed.is_none());
+            } else {
+                assert_eq!(args(parsed.unwrap()), vec![b"ECHO".to_vec(), b"hello world".to_vec()]);
+            }
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn drains_pipelined_requests_in_order() {
+        let mut parser = FrameParser::new();
+        let mut buf = BytesMut::from(&b"*1\r\n$4\r\nPING\r\nECHO hi\r\n*2\r\n$3\r\nGET\r\n$1\r\nk"[..]);
+        assert_eq!(args(parser.parse(&mut buf).unwrap().unwrap()), vec![b"PING".to_vec()]);
+        assert_eq!(
+            args(parser.parse(&mut buf).unwrap().unwrap()),
+            vec![b"ECHO".to_vec(), b"hi".to_vec()]
+        );
+        // The last request is incomplete until its terminator arrives
+        assert!(parser.parse(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"\r\n");
+        assert_eq!(
+            args(parser.parse(&mut buf).unwrap().unwrap()),
+            vec![b"GET".to_vec(), b"k".to_vec()]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn parses_inline_commands() {
+        let mut parser = FrameParser::new();
+        let mut buf = BytesMut::from(&b"  SET  key\tvalue \r\nPING\n"[..]);
+        assert_eq!(
+            args(parser.parse(&mut buf).unwrap().unwrap()),
+            vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()]
+        );
+        assert_eq!(args(parser.parse(&mut buf).unwrap().unwrap()), vec![b"PING".to_vec()]);
+        buf.extend_from_slice(b"GET k");
+        assert!(parser.parse(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn treats_empty_arrays_as_empty_requests() {
+        let mut parser = FrameParser::new();
+        let mut buf = BytesMut::from(&b"*0\r\n*-1\r\n"[..]);
+        assert!(args(parser.parse(&mut buf).unwrap().unwrap()).is_empty());
+        assert!(args(parser.parse(&mut buf).unwrap().unwrap()).is_empty());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn rejects_nested_arrays() {
+        let mut parser = FrameParser::new();
+        let mut buf = BytesMut::from(&b"*1\r\n*1\r\n*1\r\n$4\r\nPING\r\n"[..]);
+        let err = parser.parse(&mut buf).unwrap_err();
+        assert_eq!(err.to_string(), "Protocol error: expected '$', got '*'");
+    }
+
+    #[test]
+    fn rejects_malformed_bulk_strings() {
+        let mut buf = BytesMut::from(&b"*1\r\n$3\r\nPINGx\r\n"[..]);
+        assert!(FrameParser::new().parse(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*1\r\n$-1\r\n"[..]);
+        assert!(FrameParser::new().parse(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*x\r\n"[..]);
+        assert!(FrameParser::new().parse(&mut buf).is_err());
+    }
+
+    #[test]
+    fn rejects_header_lines_that_never_end() {
+        let mut buf = BytesMut::from(&b"*1\r\n$"[..]);
+        buf.extend_from_slice(&vec![b'1'; MAX_INLINE_LEN + 1]);
+        assert!(FrameParser::new().parse(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use bytes::Bytes;
+use crate::random;
+use crate::scan::ScanOrder;
+
+/// The value of a hash key.
+///
+/// Fields and values live in a `Vec` with the position of every field in a map, so
+/// HRANDFIELD reaches a random field by index; removing a field moves the last one into its
+/// place. The fields are also kept in HSCAN order, so walking a large hash with HSCAN costs
+/// each call only the fields it returns.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    entries: Vec<(Bytes, Bytes)>,
+    positions: HashMap<Bytes, usize>,
+    order: ScanOrder,
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Hash) -> bool {
+        self.len() == other.len() &&
+            self.iter().all(|(field, value)| other.get(field) == Some(value))
+    }
+}
+
+impl Eq for Hash {}
+
+impl FromIterator<(Bytes, Bytes)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (Bytes, Bytes)>>(pairs: I) -> Hash {
+        let mut hash = Hash::default();
+        for (field, value) in pairs {
+            hash.insert(field, value);
+        }
+        hash
+    }
+}
+
+impl Hash {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        let pos = *self.positions.get(field)?;
+        Some(&self.entries[pos].1)
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.positions.contains_key(field)
+    }
+
+    /// Sets `field` to `value`, returning the value it replaced
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> Option<Bytes> {
+        if let Some(pos) = self.positions.get(&field) {
+            return Some(std::mem::replace(&mut self.entries[*pos].1, value));
+        }
+        self.positions.insert(field.clone(), self.entries.len());
+        self.order.insert(field.clone());
+        self.entries.push((field, value));
+        None
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        let pos = self.positions.remove(field)?;
+        let (field, value) = self.entries.swap_remove(pos);
+        if let Some((moved, _)) = self.entries.get(pos) {
+            self.positions.insert(moved.clone(), pos);
+        }
+        self.order.remove(&field);
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.entries.iter().map(|(field, value)| (field, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.entries.iter().map(|(field, _)| field)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Bytes> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    /// The field and value at position `idx`, which must be below `len()`
+    pub fn entry_at(&self, idx: usize) -> (&Bytes, &Bytes) {
+        let (field, value) = &self.entries[idx];
+        (field, value)
+    }
+
+    /// Up to `count` distinct fields with their values, picked at random, in random order
+    pub fn random_entries(&self, count: usize) -> Vec<(&Bytes, &Bytes)> {
+        random::distinct_indexes(self.len(), count)
+            .into_iter()
+            .map(|idx| self.entry_at(idx))
+            .collect()
+    }
+
+    /// One step of HSCAN with the values of the fields it returns, see `ScanOrder::scan`
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        let (next, fields) = self.order.scan(cursor, count);
+        let pairs = fields
+            .into_iter()
+            .filter_map(|field| Some((field, self.get(field)?)))
+            .collect();
+        (next, pairs)
+    }
+}
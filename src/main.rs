@@ -6,16 +6,27 @@ use tokio::net::TcpListener;
 use tokio::time::{ sleep_until, Instant, Duration, interval };
 use std::future::Future;
 use tokio::io::{ AsyncReadExt, AsyncWriteExt };
-use bytes::BytesMut;
+use bytes::{ Bytes, BytesMut };
 use std::collections::HashMap;
 use std::sync::{ Arc, Mutex };
 // use resp_async::ValueDecoder;
 use resp::{ Decoder, Value };
 use std::io::BufReader;
+pub mod bitmap;
+pub mod blocking;
 pub mod command;
+pub mod db;
+pub mod frame;
+pub mod hash;
+pub mod random;
+pub mod reply;
+pub mod scan;
+pub mod set;
 pub mod value;
+pub mod zset;
 pub const DEFAULT_EXPIRY: u64 = 1000;
 use command::Command;
+use db::Database;
 use value::RedisValue;
 
 #[tokio::main]
@@ -24,33 +35,99 @@ async fn main() -> Result<(), Box<dyn Error>> {
     println!("Listening on 127.0.0.1:6379");
 
     // Shared database across all connections
-    let database: Arc<Mutex<HashMap<String, RedisValue>>> = Arc::new(Mutex::new(HashMap::new()));
+    let database: Arc<Mutex<Database>> = Arc::new(Mutex::new(Database::new()));
+
+    // Active expiry: keys that are never read again still get reclaimed once their deadline passes
+    let expiry_db = Arc::clone(&database);
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_millis(100));
+        loop {
+            ticker.tick().await;
+            expiry_db.lock().unwrap().active_expire_cycle();
+        }
+    });
 
     loop {
         let (mut socket, _) = listener.accept().await?;
         let db_clone = Arc::clone(&database);
 
         tokio::spawn(async move {
-            let mut buf = BytesMut::with_capacity(512);
+            let mut buf = BytesMut::with_capacity(4096);
+            let mut parser = frame::FrameParser::new();
 
             loop {
-                let mut read_buf = [0u8; 512];
-                match socket.read(&mut read_buf).await {
-                    Ok(0) => {
-                        break;
-                    }
-                    Ok(n) => {
-                        buf.extend_from_slice(&read_buf[..n]);
-                        let mut decoder = Decoder::new(BufReader::new(read_buf.as_slice()));
-                        if let Ok(command_value) = decoder.decode() {
+                // Run every complete command that is already buffered before touching the
+                // socket again, so pipelined requests are answered in order with one write
+                let mut responses: Vec<u8> = Vec::new();
+                loop {
+                    match parser.parse(&mut buf) {
+                        Ok(Some(Value::Array(args))) if args.is_empty() => {}
+                        Ok(Some(command_value)) => {
                             let command = Command::from_value(command_value);
-                            let response = command.get_return(&db_clone).await;
-                            let _ = socket.write_all(response.as_bytes()).await;
-
-                            // Clear the buffer after successful decode
-                            buf.clear();
+                            let response = command.get_return(&db_clone);
+                            tokio::pin!(response);
+                            // Only blocking commands don't finish on their first poll
+                            let finished = tokio::select! {
+                                biased;
+                                response = &mut response => Some(response),
+                                _ = std::future::ready(()) => None,
+                            };
+                            let response = match finished {
+                                Some(response) => Some(response),
+                                None => {
+                                    // Answer the commands pipelined before this one now rather
+                                    // than once it stops waiting
+                                    if !responses.is_empty() {
+                                        if socket.write_all(&responses).await.is_err() {
+                                            return;
+                                        }
+                                        responses.clear();
+                                    }
+                                    // Keep reading while blocked: a client that disconnects
+                                    // stops waiting instead of being handed data that nobody
+                                    // will read
+                                    loop {
+                                        tokio::select! {
+                                            biased;
+                                            response = &mut response => {
+                                                break Some(response);
+                                            }
+                                            read = socket.read_buf(&mut buf) => {
+                                                if !matches!(read, Ok(n) if n > 0) {
+                                                    break None;
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            };
+                            match response {
+                                Some(response) => responses.extend_from_slice(&response),
+                                None => {
+                                    return;
+                                }
+                            }
+                        }
+                        Ok(None) => {
+                            break;
                         }
+                        Err(err) => {
+                            responses.extend_from_slice(format!("-ERR {}\r\n", err).as_bytes());
+                            let _ = socket.write_all(&responses).await;
+                            return;
+                        }
+                    }
+                }
+                if !responses.is_empty() && socket.write_all(&responses).await.is_err() {
+                    break;
+                }
+
+                // Whatever is left in `buf` is the part of a request that hasn't been parsed yet
+                match socket.read_buf(&mut buf).await {
+                    Ok(0) => {
+                        break;
                     }
+                    Ok(_) => {}
                     Err(_) => {
                         break;
                     }
@@ -59,6 +136,3 @@ async fn main() -> Result<(), Box<dyn Error>> {
         });
     }
 }
-
-
-
@@ -0,0 +1,58 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::{ BuildHasher, Hasher };
+
+// Commands that pick random elements (HRANDFIELD and friends) don't need cryptographic
+// quality, so a xorshift64* generator per thread is enough and saves a dependency.
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    // RandomState is seeded by the OS; the state of xorshift must never be zero
+    RandomState::new().build_hasher().finish() | 1
+}
+
+pub fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// A random index below `len`, which must not be zero
+pub fn index(len: usize) -> usize {
+    (next_u64() % (len as u64)) as usize
+}
+
+/// Moves `count` items picked uniformly at random to the front of `items`, in random order
+pub fn shuffle_prefix<T>(items: &mut [T], count: usize) {
+    let count = count.min(items.len());
+    for idx in 0..count {
+        let pick = idx + index(items.len() - idx);
+        items.swap(idx, pick);
+    }
+}
+
+/// `count` distinct indexes below `len` picked uniformly at random, in random order. Costs
+/// O(count) however large `len` is, so callers never have to copy a whole collection.
+pub fn distinct_indexes(len: usize, count: usize) -> Vec<usize> {
+    let count = count.min(len);
+    // Floyd's algorithm: the i-th pick is taken below len - count + i, falling back to that
+    // bound itself when the pick was already taken
+    let mut taken = HashSet::with_capacity(count);
+    let mut indexes = Vec::with_capacity(count);
+    for bound in len - count..len {
+        let pick = index(bound + 1);
+        let pick = if taken.insert(pick) { pick } else { bound };
+        taken.insert(pick);
+        indexes.push(pick);
+    }
+    shuffle_prefix(&mut indexes, count);
+    indexes
+}
@@ -0,0 +1,63 @@
+// Helpers for encoding RESP replies. Replies are raw bytes because bulk strings may carry
+// arbitrary binary payloads that are not valid UTF-8.
+
+pub fn ok() -> Vec<u8> {
+    b"+OK\r\n".to_vec()
+}
+
+pub fn simple(msg: &str) -> Vec<u8> {
+    format!("+{}\r\n", msg).into_bytes()
+}
+
+pub fn error(msg: &str) -> Vec<u8> {
+    format!("-{}\r\n", msg).into_bytes()
+}
+
+pub fn integer(n: i64) -> Vec<u8> {
+    format!(":{}\r\n", n).into_bytes()
+}
+
+pub fn bulk(data: &[u8]) -> Vec<u8> {
+    let mut out = format!("${}\r\n", data.len()).into_bytes();
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+pub fn null_bulk() -> Vec<u8> {
+    b"$-1\r\n".to_vec()
+}
+
+pub fn null_array() -> Vec<u8> {
+    b"*-1\r\n".to_vec()
+}
+
+/// Wraps already encoded replies into an array reply
+pub fn array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        out.extend_from_slice(&item);
+    }
+    out
+}
+
+/// Array reply where every element is a bulk string. The elements are encoded straight into
+/// the reply as they come, so a long iterator never needs room for all of them up front.
+pub fn bulk_array<I, T>(items: I) -> Vec<u8> where I: IntoIterator<Item = T>, T: AsRef<[u8]> {
+    let mut body = Vec::new();
+    let mut len = 0;
+    for item in items {
+        let item = item.as_ref();
+        body.extend_from_slice(format!("${}\r\n", item.len()).as_bytes());
+        body.extend_from_slice(item);
+        body.extend_from_slice(b"\r\n");
+        len += 1;
+    }
+    let mut out = format!("*{}\r\n", len).into_bytes();
+    out.extend_from_slice(&body);
+    out
+}
+
+pub fn wrong_type() -> Vec<u8> {
+    error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
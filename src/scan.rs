@@ -0,0 +1,197 @@
+use std::collections::BTreeSet;
+use bytes::Bytes;
+
+// Position of an element in the iteration order of the SCAN family. It only depends on the
+// element itself (64 bit FNV-1a), so the order survives the collection growing, shrinking
+// or being rehashed between calls.
+fn position(item: &[u8]) -> u64 {
+    item.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ (*byte as u64)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// The elements of a collection in SCAN order, kept up to date next to the collection so
+/// each step of an iteration starts right where the previous one stopped.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOrder {
+    items: BTreeSet<(u64, Bytes)>,
+}
+
+impl ScanOrder {
+    pub fn insert(&mut self, item: Bytes) {
+        self.items.insert((position(&item), item));
+    }
+
+    pub fn remove(&mut self, item: &Bytes) {
+        self.items.remove(&(position(item), item.clone()));
+    }
+
+    /// One step of a cursor based iteration: up to `count` items whose position is at or
+    /// after `cursor`, in position order, and the cursor to continue from (0 once done).
+    ///
+    /// Every element present for the whole iteration is returned exactly once; elements
+    /// added or removed in the meantime may or may not be. A step costs O(log n + count).
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&Bytes>) {
+        let mut page = Vec::with_capacity(count);
+        for (pos, item) in self.items.range((cursor, Bytes::new())..) {
+            if page.len() == count {
+                return (*pos, page);
+            }
+            page.push(item);
+        }
+        (0, page)
+    }
+}
+
+/// Glob-style matching as used by MATCH and KEYS: `*`, `?`, `[abc]`, `[^a-z]` and `\` to
+/// escape the next character
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let mut pattern_rest = pattern;
+    let mut text_rest = text;
+    // The pattern after the last '*' and the text from where that star stopped swallowing.
+    // On a mismatch only this star takes one more byte, so matching costs at most
+    // O(pattern * text) instead of trying every split point of every star.
+    let mut backtrack: Option<(&[u8], &[u8])> = None;
+    loop {
+        if let Some(after_star) = pattern_rest.strip_prefix(b"*") {
+            backtrack = Some((after_star, text_rest));
+            pattern_rest = after_star;
+            continue;
+        }
+        let Some((&ch, text_after)) = text_rest.split_first() else {
+            // Only stars can match what is left once the text runs out
+            return pattern_rest.iter().all(|byte| *byte == b'*');
+        };
+        if let Some(pattern_after) = match_one(pattern_rest, ch) {
+            pattern_rest = pattern_after;
+            text_rest = text_after;
+            continue;
+        }
+        match backtrack {
+            Some((after_star, [_, swallowed @ ..])) => {
+                backtrack = Some((after_star, swallowed));
+                pattern_rest = after_star;
+                text_rest = swallowed;
+            }
+            _ => {
+                return false;
+            }
+        }
+    }
+}
+
+// Matches `ch` against the element at the start of `pattern`: a literal, `?`, a class or an
+// escaped character. Returns the pattern after that element when it matches.
+fn match_one(pattern: &[u8], ch: u8) -> Option<&[u8]> {
+    match pattern.split_first()? {
+        (b'?', rest) => Some(rest),
+        (b'[', rest) => {
+            let (matched, rest) = match_class(rest, ch);
+            matched.then_some(rest)
+        }
+        (b'\\', [escaped, rest @ ..]) => (*escaped == ch).then_some(rest),
+        (literal, rest) => (*literal == ch).then_some(rest),
+    }
+}
+
+// Matches `ch` against the class that starts after a '[', returning whether it matched and
+// the pattern after the closing ']'. An unterminated class runs to the end of the pattern.
+fn match_class(mut pattern: &[u8], ch: u8) -> (bool, &[u8]) {
+    let negate = pattern.first() == Some(&b'^');
+    if negate {
+        pattern = &pattern[1..];
+    }
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => {
+                break;
+            }
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == ch;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end { (*start, *end) } else { (*end, *start) };
+                matched |= (low..=high).contains(&ch);
+                pattern = rest;
+            }
+            [literal, rest @ ..] => {
+                matched |= *literal == ch;
+                pattern = rest;
+            }
+        }
+    }
+    (matched != negate, pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("*", "anything", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hello world", false),
+            ("*llo", "hello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hallo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("a*b*c", "aXXbYYc", true),
+            ("a*b*c", "aXXbYY", false),
+            ("user:*:name", "user:42:name", true),
+            ("**", "", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern.as_bytes(), text.as_bytes()), *expected, "{} ~ {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn many_stars_do_not_backtrack_exponentially() {
+        let text = vec![b'a'; 60];
+        assert!(!glob_match(b"*a*a*a*a*a*a*b", &text));
+        assert!(glob_match(b"*a*a*a*a*a*a*", &text));
+    }
+
+    #[test]
+    fn scans_every_item_once() {
+        let mut order = ScanOrder::default();
+        for idx in 0..1000 {
+            order.insert(Bytes::from(format!("item{}", idx)));
+        }
+        order.remove(&Bytes::from("item7"));
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, page) = order.scan(cursor, 10);
+            assert!(page.len() <= 10);
+            seen.extend(page.into_iter().cloned());
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        seen.sort();
+        let mut expected: Vec<Bytes> = (0..1000)
+            .filter(|idx| *idx != 7)
+            .map(|idx| Bytes::from(format!("item{}", idx)))
+            .collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use bytes::Bytes;
+use crate::random;
+use crate::value::parse_i64_strict;
+
+// Largest set kept in the integer encoding, Redis' set-max-intset-entries default
+pub const MAX_INTSET_ENTRIES: usize = 512;
+
+/// The value of a set key.
+///
+/// Small sets of integers are kept like Redis' intset: a sorted `Vec<i64>` costing 8 bytes
+/// per member and searched by bisection. Adding a member that isn't an integer in canonical
+/// form, or growing past `MAX_INTSET_ENTRIES`, converts the set to a hash table for good.
+///
+/// Both encodings can reach a member by its position, which is how SPOP and SRANDMEMBER
+/// pick random members without copying the set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    HashTable(Members),
+}
+
+/// The members of a hash table encoded set, kept in a `Vec` with the position of each one
+/// in a map. Removing a member moves the last one into its place.
+#[derive(Debug, Clone, Default)]
+pub struct Members {
+    items: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
+}
+
+impl PartialEq for Members {
+    fn eq(&self, other: &Members) -> bool {
+        self.items.len() == other.items.len() &&
+            self.items.iter().all(|member| other.positions.contains_key(member))
+    }
+}
+
+impl Eq for Members {}
+
+impl Members {
+    fn insert(&mut self, member: Bytes) -> bool {
+        if self.positions.contains_key(&member) {
+            return false;
+        }
+        self.positions.insert(member.clone(), self.items.len());
+        self.items.push(member);
+        true
+    }
+
+    fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(pos) = self.positions.remove(member) else {
+            return false;
+        };
+        self.items.swap_remove(pos);
+        if let Some(moved) = self.items.get(pos) {
+            self.positions.insert(moved.clone(), pos);
+        }
+        true
+    }
+}
+
+impl Default for Set {
+    fn default() -> Set {
+        Set::IntSet(Vec::new())
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(members: I) -> Set {
+        let mut set = Set::default();
+        for member in members {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len(),
+            Set::HashTable(members) => members.items.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => {
+                parse_i64_strict(member).is_some_and(|int| ints.binary_search(&int).is_ok())
+            }
+            Set::HashTable(members) => members.positions.contains_key(member),
+        }
+    }
+
+    /// Adds `member`, returning false when it was already there
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        match self {
+            Set::IntSet(ints) => {
+                let int = parse_i64_strict(&member);
+                match int.map(|int| (int, ints.binary_search(&int))) {
+                    Some((_, Ok(_))) => false,
+                    Some((int, Err(pos))) if ints.len() < MAX_INTSET_ENTRIES => {
+                        ints.insert(pos, int);
+                        true
+                    }
+                    _ => {
+                        self.convert();
+                        self.insert(member)
+                    }
+                }
+            }
+            Set::HashTable(members) => members.insert(member),
+        }
+    }
+
+    /// Removes `member`, returning false when it wasn't there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(ints) => {
+                let found = parse_i64_strict(member).and_then(|int| ints.binary_search(&int).ok());
+                match found {
+                    Some(pos) => {
+                        ints.remove(pos);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Set::HashTable(members) => members.remove(member),
+        }
+    }
+
+    /// The members, integers in ascending order for the integer encoding
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Set::IntSet(ints) => Box::new(ints.iter().map(|int| Bytes::from(int.to_string()))),
+            Set::HashTable(members) => Box::new(members.items.iter().cloned()),
+        }
+    }
+
+    /// The member at position `idx`, which must be below `len()`
+    pub fn member_at(&self, idx: usize) -> Bytes {
+        match self {
+            Set::IntSet(ints) => Bytes::from(ints[idx].to_string()),
+            Set::HashTable(members) => members.items[idx].clone(),
+        }
+    }
+
+    /// Up to `count` distinct members picked at random, in random order
+    pub fn random_members(&self, count: usize) -> Vec<Bytes> {
+        random::distinct_indexes(self.len(), count)
+            .into_iter()
+            .map(|idx| self.member_at(idx))
+            .collect()
+    }
+
+    /// The name OBJECT ENCODING reports for this set
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::IntSet(_) => "intset",
+            Set::HashTable(_) => "hashtable",
+        }
+    }
+
+    fn convert(&mut self) {
+        if let Set::IntSet(ints) = self {
+            let mut members = Members::default();
+            for int in ints.iter() {
+                members.insert(Bytes::from(int.to_string()));
+            }
+            *self = Set::HashTable(members);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn members(set: &Set) -> Vec<Bytes> {
+        let mut members: Vec<Bytes> = set.iter().collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn keeps_integers_in_an_intset() {
+        let set: Set = ["3", "-7", "12", "3"].into_iter().map(Bytes::from).collect();
+        assert_eq!(set.encoding(), "intset");
+        assert_eq!(set.len(), 3);
+        // Integers come out sorted and in canonical form
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec!["-7", "3", "12"]);
+        assert!(set.contains(b"12"));
+        assert!(!set.contains(b"4"));
+        assert!(!set.contains(b"abc"));
+    }
+
+    #[test]
+    fn converts_on_a_member_that_is_not_an_integer() {
+        let mut set: Set = ["1", "2"].into_iter().map(Bytes::from).collect();
+        assert!(set.insert(Bytes::from("x")));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(members(&set), vec!["1", "2", "x"]);
+        assert!(set.contains(b"1"));
+        assert!(!set.insert(Bytes::from("2")));
+    }
+
+    #[test]
+    fn converts_on_integers_not_in_canonical_form() {
+        for member in ["01", "+1", " 1", "1.0", "-0"] {
+            let mut set: Set = ["1"].into_iter().map(Bytes::from).collect();
+            assert!(set.insert(Bytes::from(member)), "{}", member);
+            assert_eq!(set.encoding(), "hashtable", "{}", member);
+            // The non-canonical spelling is a member of its own, distinct from "1"
+            assert_eq!(set.len(), 2, "{}", member);
+            assert!(set.contains(member.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn converts_when_growing_past_the_limit() {
+        let mut set = Set::default();
+        for int in 0..MAX_INTSET_ENTRIES {
+            assert!(set.insert(Bytes::from(int.to_string())));
+        }
+        assert_eq!(set.encoding(), "intset");
+        // Re-adding a member of a full intset doesn't convert it
+        assert!(!set.insert(Bytes::from("0")));
+        assert_eq!(set.encoding(), "intset");
+        assert!(set.insert(Bytes::from(MAX_INTSET_ENTRIES.to_string())));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), MAX_INTSET_ENTRIES + 1);
+        for int in 0..=MAX_INTSET_ENTRIES {
+            assert!(set.contains(int.to_string().as_bytes()));
+        }
+        // Shrinking again doesn't go back to an intset
+        for int in 0..MAX_INTSET_ENTRIES {
+            assert!(set.remove(int.to_string().as_bytes()));
+        }
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(members(&set), vec![MAX_INTSET_ENTRIES.to_string()]);
+    }
+
+    #[test]
+    fn removes_and_indexes_members_after_converting() {
+        let mut set: Set = (0..10).map(|int| Bytes::from(int.to_string())).collect();
+        set.insert(Bytes::from("a"));
+        assert!(set.remove(b"0"));
+        assert!(!set.remove(b"0"));
+        assert!(set.remove(b"a"));
+        assert!(!set.contains(b"a"));
+        let mut expected: Vec<Bytes> = (1..10).map(|int| Bytes::from(int.to_string())).collect();
+        expected.sort();
+        assert_eq!(members(&set), expected);
+        // Positions stay dense after swap removals
+        let mut by_position: Vec<Bytes> = (0..set.len()).map(|idx| set.member_at(idx)).collect();
+        by_position.sort();
+        assert_eq!(by_position, expected);
+    }
+
+    #[test]
+    fn picks_distinct_random_members() {
+        for set in [
+            (0..100).map(|int| Bytes::from(int.to_string())).collect::<Set>(),
+            (0..100).map(|int| Bytes::from(format!("m{}", int))).collect::<Set>(),
+        ] {
+            let picked = set.random_members(30);
+            assert_eq!(picked.len(), 30);
+            assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 30);
+            assert!(picked.iter().all(|member| set.contains(member)));
+            // Asking for more than the set holds returns every member once
+            let mut all = set.random_members(1000);
+            all.sort();
+            assert_eq!(all, members(&set));
+        }
+    }
+}
@@ -1,19 +1,96 @@
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque };
+use bytes::Bytes;
+use crate::hash::Hash;
+use crate::reply;
+use crate::set::Set;
+use crate::zset::ZSet;
+
+// Largest string value we accept, matching Redis' proto-max-bulk-len default
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Parses a string value as a 64 bit integer the way Redis does: no sign other than a
+/// leading '-', no whitespace and no leading zeros, so that "007" or "+7" are not counters
+pub fn parse_i64_strict(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    if digits[0] == b'0' && (digits.len() > 1 || value.len() > 1) {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Parses a string value as a float, rejecting NaN, infinities and surrounding whitespace
+pub fn parse_f64_strict(value: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(value).ok()?;
+    if text.is_empty() || text.trim() != text {
+        return None;
+    }
+    let parsed: f64 = text.parse().ok()?;
+    parsed.is_finite().then_some(parsed)
+}
+
+/// Formats a float the way Redis replies with it: integral values without a fractional
+/// part and everything else in its shortest round-trip form
+pub fn format_float(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else {
+        // `{}` never uses exponent notation and drops the trailing ".0" of integral values
+        format!("{}", value)
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum RedisValue {
-    String(String),
-    List(Vec<String>),
-    Hash(HashMap<String, String>),
+    String(Bytes),
+    // A deque so pushes and pops at either end are O(1) and happen in place
+    List(VecDeque<Bytes>),
+    Hash(Hash),
+    Set(Set),
+    ZSet(ZSet),
     Stream(Vec<StreamEntry>),
 }
 
+/// Which end of a list a push or pop works on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    pub fn pop(&self, list: &mut VecDeque<Bytes>) -> Option<Bytes> {
+        match self {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        }
+    }
+
+    /// Pops up to `count` elements, in the order they come off this end
+    pub fn pop_many(&self, list: &mut VecDeque<Bytes>, count: usize) -> Vec<Bytes> {
+        let count = count.min(list.len());
+        match self {
+            ListEnd::Left => list.drain(..count).collect(),
+            ListEnd::Right => (0..count).filter_map(|_| list.pop_back()).collect(),
+        }
+    }
+
+    pub fn push(&self, list: &mut VecDeque<Bytes>, item: Bytes) {
+        match self {
+            ListEnd::Left => list.push_front(item),
+            ListEnd::Right => list.push_back(item),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
 pub struct StreamEntry {
     pub id: String,
     pub milliseconds_time: usize,
     pub sequence_number: usize,
-    pub fields: HashMap<String, String>,
+    pub fields: HashMap<Bytes, Bytes>,
 }
 
 impl StreamEntry {
@@ -26,16 +103,16 @@ impl StreamEntry {
             Some(old_entry) => {
                 // Compare with existing entry
                 if old_entry.milliseconds_time > new.milliseconds_time {
-                    return Some(false);
+                    Some(false)
                 } else if old_entry.milliseconds_time == new.milliseconds_time {
                     if old_entry.sequence_number >= new.sequence_number {
-                        return Some(false);
+                        Some(false)
                     } else {
-                        return Some(true);
+                        Some(true)
                     }
                 } else {
                     // old_entry.milliseconds_time < new.milliseconds_time
-                    return Some(true);
+                    Some(true)
                 }
             }
             None => {
@@ -43,14 +120,14 @@ impl StreamEntry {
                 if new.milliseconds_time == 0 && new.sequence_number == 0 {
                     return None;
                 }
-                return Some(true);
+                Some(true)
             }
         }
     }
     // this is very fragile as giving wrong entry_ids will break this unwrap() inside a the map(||)
     pub fn from(
         id: String,
-        fields: HashMap<String, String>,
+        fields: HashMap<Bytes, Bytes>,
         old: Option<&StreamEntry>
     ) -> StreamEntry {
         let mut entry_id: Vec<String> = id
@@ -97,71 +174,63 @@ impl StreamEntry {
 }
 
 impl RedisValue {
-    pub fn from_string(value: String) -> RedisValue {
+    pub fn from_string(value: Bytes) -> RedisValue {
         RedisValue::String(value)
     }
-    pub fn from_list(list: Vec<String>) -> RedisValue {
-        RedisValue::List(list)
+    pub fn from_list(list: Vec<Bytes>) -> RedisValue {
+        RedisValue::List(VecDeque::from(list))
     }
     pub fn from_stream(entries: Vec<StreamEntry>) -> Self {
         RedisValue::Stream(entries)
     }
 
-    pub fn get_response(&self) -> String {
+    /// True for a collection that has no elements left. Redis never keeps empty lists,
+    /// hashes etc. around, so commands delete the key once this becomes true.
+    pub fn is_empty_collection(&self) -> bool {
         match self {
-            // Simple string value - return as bulk string
-            RedisValue::String(s) => { format!("${}\r\n{}\r\n", s.len(), s) }
-
-            // List - return as array of bulk strings
-            RedisValue::List(list) => {
-                if list.is_empty() {
-                    "*0\r\n".to_string()
-                } else {
-                    let mut response = format!("*{}\r\n", list.len());
-                    for item in list {
-                        response.push_str(&format!("${}\r\n{}\r\n", item.len(), item));
-                    }
-                    response
-                }
-            }
-
-            // Hash - return as array of field-value pairs (flattened)
-            RedisValue::Hash(hash) => {
-                if hash.is_empty() {
-                    "*0\r\n".to_string()
-                } else {
-                    // Each key-value pair becomes 2 elements in the array
-                    let mut response = format!("*{}\r\n", hash.len() * 2);
-                    for (key, value) in hash {
-                        response.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
-                        response.push_str(&format!("${}\r\n{}\r\n", value.len(), value));
-                    }
-                    response
-                }
-            }
-
-            RedisValue::Stream(_) => "+stream\r\n".to_string(),
+            RedisValue::String(_) | RedisValue::Stream(_) => false,
+            RedisValue::List(list) => list.is_empty(),
+            RedisValue::Hash(hash) => hash.is_empty(),
+            RedisValue::Set(set) => set.is_empty(),
+            RedisValue::ZSet(zset) => zset.is_empty(),
         }
     }
 
     // The TYPE command returns the type of value stored at a given key. These types include: string, list, set, zset, hash, stream, and vectorset.
     // Server should respond with +string\r\n, which is string encoded as a simple string.
-    pub fn get_type_response(&self) -> String {
+    pub fn get_type_response(&self) -> Vec<u8> {
         match self {
-            // Simple string value - return as bulk string
-            RedisValue::String(s) => { "+string\r\n".to_string() }
+            RedisValue::String(_) => reply::simple("string"),
+
+            RedisValue::List(_) => reply::simple("list"),
 
-            // List - return as array of bulk strings
-            RedisValue::List(list) => { "+list\r\n".to_string() }
+            RedisValue::Hash(_) => reply::simple("hash"),
 
-            RedisValue::Hash(list) => { "+stream\r\n".to_string() }
+            RedisValue::Set(_) => reply::simple("set"),
 
-            RedisValue::Stream(_) => "+stream\r\n".to_string(),
+            RedisValue::ZSet(_) => reply::simple("zset"),
+
+            RedisValue::Stream(_) => reply::simple("stream"),
+        }
+    }
+
+    /// The internal representation OBJECT ENCODING reports, named as in Redis
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            RedisValue::String(s) if parse_i64_strict(s).is_some() => "int",
+            // Redis keeps short strings in the same allocation as the object header
+            RedisValue::String(s) if s.len() <= 44 => "embstr",
+            RedisValue::String(_) => "raw",
+            RedisValue::List(_) => "quicklist",
+            RedisValue::Hash(_) => "hashtable",
+            RedisValue::Set(set) => set.encoding(),
+            RedisValue::ZSet(_) => "skiplist",
+            RedisValue::Stream(_) => "stream",
         }
     }
 
     /// Returns null response when key doesn't exist
-    pub fn get_null_response() -> String {
-        "$-1\r\n".to_string()
+    pub fn get_null_response() -> Vec<u8> {
+        reply::null_bulk()
     }
 }
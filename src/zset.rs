@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use bytes::Bytes;
+use crate::random;
+
+// Same limits as Redis' zskiplist: at most 32 levels, each level holding a quarter of the
+// nodes of the one below
+const MAX_LEVEL: usize = 32;
+const LEVEL_PROBABILITY: u64 = 4;
+
+// Nodes live in an arena and link to each other by index; the head sentinel is always node 0
+const HEAD: usize = 0;
+const NIL: usize = usize::MAX;
+
+/// An interval of scores as given to ZCOUNT and friends, where "(" makes a bound exclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub min_exclusive: bool,
+    pub max: f64,
+    pub max_exclusive: bool,
+}
+
+impl ScoreRange {
+    pub fn above_min(&self, score: f64) -> bool {
+        if self.min_exclusive { score > self.min } else { score >= self.min }
+    }
+
+    pub fn below_max(&self, score: f64) -> bool {
+        if self.max_exclusive { score < self.max } else { score <= self.max }
+    }
+}
+
+/// One end of a lexicographical range of ZRANGEBYLEX and friends: "-", "+", "[member" or "(member"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    NegInf,
+    PosInf,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    pub fn above_min(&self, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::NegInf => true,
+            LexBound::PosInf => false,
+            LexBound::Inclusive(min) => member >= &min[..],
+            LexBound::Exclusive(min) => member > &min[..],
+        }
+    }
+
+    pub fn below_max(&self, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::NegInf => false,
+            LexBound::PosInf => true,
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+        }
+    }
+}
+
+/// The value of a sorted set key.
+///
+/// Like Redis, members are indexed twice: `scores` answers "what is the score of x" in O(1)
+/// and the skiplist keeps (score, member) pairs in order, with the span of every link so
+/// ranks are found in O(log n) as well. Scores are never NaN.
+#[derive(Debug, Clone, Default)]
+pub struct ZSet {
+    scores: HashMap<Bytes, f64>,
+    list: SkipList,
+}
+
+// Scores are never NaN, so comparing them is a proper equivalence
+impl PartialEq for ZSet {
+    fn eq(&self, other: &ZSet) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl Eq for ZSet {}
+
+impl ZSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds `member` or moves it to `score`. Returns true when the member is new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                if old != score {
+                    self.list.delete(old, &member);
+                    self.list.insert(score, member);
+                }
+                false
+            }
+            None => {
+                self.list.insert(score, member);
+                true
+            }
+        }
+    }
+
+    /// Removes `member`, returning false when it wasn't there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.list.delete(score, member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Position of `member` counting from the lowest score, starting at 0
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.list.count_while(|node| precedes(node.score, &node.member, score, member)))
+    }
+
+    /// Number of members whose score falls in `range`
+    pub fn count_in_range(&self, range: &ScoreRange) -> usize {
+        self.score_range_ranks(range).len()
+    }
+
+    /// Ranks of the members whose score falls in `range`
+    pub fn score_range_ranks(&self, range: &ScoreRange) -> Range<usize> {
+        let start = self.list.count_while(|node| !range.above_min(node.score));
+        let end = self.list.count_while(|node| range.below_max(node.score));
+        start..end.max(start)
+    }
+
+    /// Ranks of the members that fall in `range` when compared byte by byte. Only meaningful
+    /// when all members have the same score, as in Redis.
+    pub fn lex_range_ranks(&self, range: &LexRange) -> Range<usize> {
+        let start = self.list.count_while(|node| !range.above_min(&node.member));
+        let end = self.list.count_while(|node| range.below_max(&node.member));
+        start..end.max(start)
+    }
+
+    /// The members at `ranks` with their scores, lowest score first or, with `rev`, highest first
+    pub fn slice(&self, ranks: Range<usize>, rev: bool) -> Vec<(&Bytes, f64)> {
+        let ranks = ranks.start..ranks.end.min(self.len());
+        if ranks.is_empty() {
+            return Vec::new();
+        }
+        let count = ranks.len();
+        if rev {
+            self.list.walk(self.list.node_at(ranks.end - 1), true).take(count).collect()
+        } else {
+            self.list.walk(self.list.node_at(ranks.start), false).take(count).collect()
+        }
+    }
+
+    /// Members with their scores, lowest score first
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.list.walk(self.list.first(), false)
+    }
+}
+
+// Whether (score, member) sorts before (other_score, other_member): by score, then by the
+// bytes of the member
+fn precedes(score: f64, member: &[u8], other_score: f64, other_member: &[u8]) -> bool {
+    score < other_score || (score == other_score && member < other_member)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    next: usize,
+    // How many nodes the link moves forward; links to NIL count up to the end of the list
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    back: usize,
+    levels: Vec<Level>,
+}
+
+#[derive(Debug, Clone)]
+struct SkipList {
+    nodes: Vec<Node>,
+    // Arena slots of deleted nodes, reused by later inserts
+    free: Vec<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> SkipList {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            back: NIL,
+            levels: vec![Level { next: NIL, span: 0 }; MAX_LEVEL],
+        };
+        SkipList { nodes: vec![head], free: Vec::new(), level: 1, len: 0 }
+    }
+}
+
+impl SkipList {
+    fn random_level() -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL && random::next_u64().is_multiple_of(LEVEL_PROBABILITY) {
+            level += 1;
+        }
+        level
+    }
+
+    fn first(&self) -> usize {
+        self.nodes[HEAD].levels[0].next
+    }
+
+    // Walks down from the top level to the last node before (score, member) on every level,
+    // returning those nodes and their ranks
+    fn find_predecessors(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            loop {
+                let link = self.nodes[x].levels[i];
+                if link.next == NIL {
+                    break;
+                }
+                let next = &self.nodes[link.next];
+                if !precedes(next.score, &next.member, score, member) {
+                    break;
+                }
+                rank[i] += link.span;
+                x = link.next;
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+
+    // The caller makes sure the member isn't in the list yet
+    fn insert(&mut self, score: f64, member: Bytes) {
+        let (mut update, mut rank) = self.find_predecessors(score, &member);
+        let level = SkipList::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node { member, score, back: NIL, levels: vec![Level { next: NIL, span: 0 }; level] };
+        let new = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+            let prev_link = self.nodes[prev].levels[i];
+            // rank[0] - rank[i] nodes separate update[i] from the insertion point
+            let skipped = rank[0] - rank[i];
+            self.nodes[new].levels[i] = Level { next: prev_link.next, span: prev_link.span - skipped };
+            self.nodes[prev].levels[i] = Level { next: new, span: skipped + 1 };
+        }
+        // Links above the new node's height now jump over one more node
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+
+        self.nodes[new].back = if update[0] == HEAD { NIL } else { update[0] };
+        let next = self.nodes[new].levels[0].next;
+        if next != NIL {
+            self.nodes[next].back = new;
+        }
+        self.len += 1;
+    }
+
+    // Unlinks the node holding exactly (score, member), if there is one
+    fn delete(&mut self, score: f64, member: &[u8]) -> bool {
+        let (update, _) = self.find_predecessors(score, member);
+        let target = self.nodes[update[0]].levels[0].next;
+        if target == NIL || self.nodes[target].score != score || self.nodes[target].member != member {
+            return false;
+        }
+
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            let target_link = self.nodes[target].levels.get(i).copied();
+            let prev_link = &mut self.nodes[*prev].levels[i];
+            match target_link {
+                Some(target_link) if prev_link.next == target => {
+                    prev_link.span += target_link.span;
+                    prev_link.span -= 1;
+                    prev_link.next = target_link.next;
+                }
+                _ => {
+                    prev_link.span -= 1;
+                }
+            }
+        }
+        let next = self.nodes[target].levels[0].next;
+        if next != NIL {
+            self.nodes[next].back = self.nodes[target].back;
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].next == NIL {
+            self.level -= 1;
+        }
+        self.len -= 1;
+
+        // Drop the member now rather than when the slot is reused
+        self.nodes[target].member = Bytes::new();
+        self.nodes[target].levels = Vec::new();
+        self.free.push(target);
+        true
+    }
+
+    /// How many nodes from the start of the list satisfy `pred`, which must hold for a
+    /// prefix of the list and not after it
+    fn count_while(&self, pred: impl Fn(&Node) -> bool) -> usize {
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for i in (0..self.level).rev() {
+            loop {
+                let link = self.nodes[x].levels[i];
+                if link.next == NIL || !pred(&self.nodes[link.next]) {
+                    break;
+                }
+                traversed += link.span;
+                x = link.next;
+            }
+        }
+        traversed
+    }
+
+    // The node at `rank` (from 0), or NIL past the end
+    fn node_at(&self, rank: usize) -> usize {
+        // Ranks are counted from 1 along the links, the head being 0
+        let target = rank + 1;
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for i in (0..self.level).rev() {
+            loop {
+                let link = self.nodes[x].levels[i];
+                if link.next == NIL || traversed + link.span > target {
+                    break;
+                }
+                traversed += link.span;
+                x = link.next;
+            }
+            if traversed == target {
+                return x;
+            }
+        }
+        NIL
+    }
+
+    // Members and scores from node `start` towards the end, or towards the front with `rev`
+    fn walk(&self, start: usize, rev: bool) -> impl Iterator<Item = (&Bytes, f64)> {
+        let mut x = start;
+        std::iter::from_fn(move || {
+            if x == NIL {
+                return None;
+            }
+            let node = &self.nodes[x];
+            x = if rev { node.back } else { node.levels[0].next };
+            Some((&node.member, node.score))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The (score, member) pairs of `model` in skiplist order
+    fn sorted(model: &HashMap<Bytes, f64>) -> Vec<(Bytes, f64)> {
+        let mut pairs: Vec<(Bytes, f64)> = model
+            .iter()
+            .map(|(member, score)| (member.clone(), *score))
+            .collect();
+        pairs.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then_with(|| a.0.cmp(&b.0)));
+        pairs
+    }
+
+    fn owned(pairs: Vec<(&Bytes, f64)>) -> Vec<(Bytes, f64)> {
+        pairs
+            .into_iter()
+            .map(|(member, score)| (member.clone(), score))
+            .collect()
+    }
+
+    // Every link on every level must span exactly the nodes between its two ends
+    fn check_spans(list: &SkipList) {
+        let mut ranks = HashMap::new();
+        let mut x = list.first();
+        let mut rank = 1;
+        while x != NIL {
+            ranks.insert(x, rank);
+            x = list.nodes[x].levels[0].next;
+            rank += 1;
+        }
+        for i in 0..list.level {
+            let mut x = HEAD;
+            let mut at = 0;
+            loop {
+                let link = list.nodes[x].levels[i];
+                if link.next == NIL {
+                    assert!(at + link.span <= list.len, "level {} runs past the end", i);
+                    break;
+                }
+                assert_eq!(at + link.span, ranks[&link.next], "span on level {}", i);
+                at = ranks[&link.next];
+                x = link.next;
+            }
+        }
+        for i in list.level..MAX_LEVEL {
+            assert_eq!(list.nodes[HEAD].levels[i].next, NIL);
+        }
+    }
+
+    fn check(zset: &ZSet, model: &HashMap<Bytes, f64>) {
+        let expected = sorted(model);
+        check_spans(&zset.list);
+        assert_eq!(zset.len(), expected.len());
+        assert_eq!(owned(zset.iter().collect()), expected);
+        for (rank, (member, _)) in expected.iter().enumerate() {
+            assert_eq!(zset.rank(member), Some(rank));
+            assert_eq!(&zset.list.nodes[zset.list.node_at(rank)].member, member);
+        }
+        assert_eq!(zset.list.node_at(expected.len()), NIL);
+        let mut reversed = expected.clone();
+        reversed.reverse();
+        assert_eq!(owned(zset.slice(0..expected.len(), true)), reversed);
+    }
+
+    #[test]
+    fn keeps_ranks_through_interleaved_inserts_and_deletes() {
+        let mut zset = ZSet::default();
+        let mut model = HashMap::new();
+        for step in 0..3000 {
+            let member = Bytes::from(format!("m{}", random::index(300)));
+            // Few distinct scores, so ties are broken by member often
+            let score = random::index(20) as f64;
+            if random::index(3) == 0 {
+                assert_eq!(zset.remove(&member), model.remove(&member).is_some());
+            } else {
+                assert_eq!(zset.insert(member.clone(), score), model.insert(member, score).is_none());
+            }
+            if step % 100 == 0 {
+                check(&zset, &model);
+            }
+        }
+        check(&zset, &model);
+        assert!(!zset.remove(b"missing"));
+        assert_eq!(zset.rank(b"missing"), None);
+    }
+
+    #[test]
+    fn answers_score_and_lex_ranges() {
+        let mut zset = ZSet::default();
+        let mut model = HashMap::new();
+        for idx in 0..200 {
+            let member = Bytes::from(format!("m{:03}", idx));
+            zset.insert(member.clone(), (idx % 50) as f64);
+            model.insert(member, (idx % 50) as f64);
+        }
+        for idx in (0..200).step_by(3) {
+            let member = Bytes::from(format!("m{:03}", idx));
+            zset.remove(&member);
+            model.remove(&member);
+        }
+        let expected = sorted(&model);
+
+        for (min, min_exclusive, max, max_exclusive) in [
+            (10.0, false, 20.0, false),
+            (10.0, true, 20.0, true),
+            (f64::NEG_INFINITY, false, f64::INFINITY, false),
+            (30.0, false, 10.0, false),
+            (49.0, true, 60.0, false),
+        ] {
+            let range = ScoreRange { min, min_exclusive, max, max_exclusive };
+            let matching: Vec<(Bytes, f64)> = expected
+                .iter()
+                .filter(|(_, score)| range.above_min(*score) && range.below_max(*score))
+                .cloned()
+                .collect();
+            let ranks = zset.score_range_ranks(&range);
+            assert_eq!(zset.count_in_range(&range), matching.len());
+            assert_eq!(owned(zset.slice(ranks.clone(), false)), matching);
+            let mut reversed = matching.clone();
+            reversed.reverse();
+            assert_eq!(owned(zset.slice(ranks, true)), reversed);
+        }
+
+        // Lex ranges assume a single score
+        let mut flat = ZSet::default();
+        for (member, _) in &expected {
+            flat.insert(member.clone(), 0.0);
+        }
+        let range = LexRange {
+            min: LexBound::Inclusive(Bytes::from("m050")),
+            max: LexBound::Exclusive(Bytes::from("m100")),
+        };
+        let mut matching: Vec<Bytes> = expected
+            .iter()
+            .map(|(member, _)| member.clone())
+            .filter(|member| range.above_min(member) && range.below_max(member))
+            .collect();
+        matching.sort();
+        let members: Vec<Bytes> = flat
+            .slice(flat.lex_range_ranks(&range), false)
+            .into_iter()
+            .map(|(member, _)| member.clone())
+            .collect();
+        assert_eq!(members, matching);
+        let everything = LexRange { min: LexBound::NegInf, max: LexBound::PosInf };
+        assert_eq!(flat.lex_range_ranks(&everything), 0..expected.len());
+        let nothing = LexRange { min: LexBound::PosInf, max: LexBound::NegInf };
+        assert!(flat.lex_range_ranks(&nothing).is_empty());
+    }
+
+    #[test]
+    fn slices_past_the_end_are_clamped() {
+        let mut zset = ZSet::default();
+        zset.insert(Bytes::from("a"), 1.0);
+        zset.insert(Bytes::from("b"), 2.0);
+        assert_eq!(owned(zset.slice(1..10, false)), vec![(Bytes::from("b"), 2.0)]);
+        assert_eq!(owned(zset.slice(0..10, true)), vec![(Bytes::from("b"), 2.0), (Bytes::from("a"), 1.0)]);
+        assert!(zset.slice(5..10, false).is_empty());
+    }
+}